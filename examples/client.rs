@@ -31,10 +31,10 @@ fn main() {
         println!("[client] event: {:#?}", e);
 
         match e {
-            Event::Connect(ref p) => {
+            Event::Connect(ref p, ..) => {
                 break p.clone();
             }
-            Event::Disconnect(ref p, r) => {
+            Event::Disconnect(ref p, _, r) => {
                 println!("connection NOT successful, peer: {:?}, reason: {}", p, r);
                 std::process::exit(0);
             }
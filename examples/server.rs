@@ -21,7 +21,7 @@ fn main() {
 
     loop {
         match host.service(1000).expect("service failed") {
-            Some(Event::Connect(_)) => println!("new connection!"),
+            Some(Event::Connect(..)) => println!("new connection!"),
             Some(Event::Disconnect(..)) => println!("disconnect!"),
             Some(Event::Receive {
                 channel_id,
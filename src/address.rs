@@ -1,5 +1,9 @@
-use std::ffi::CString;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+use std::os::raw::{c_char, c_int};
+use std::str::FromStr;
 
 use byteorder::{NetworkEndian, ReadBytesExt};
 
@@ -8,12 +12,47 @@ use crate::Error;
 use enet_sys::ENetAddress;
 
 /// An IPv4 address that can be used with the ENet API.
-#[derive(Clone, Debug, PartialEq, Eq)]
+///
+/// IPv6 is not supported: the ENet library linked through `enet-sys` only supports IPv4,
+/// and its `ENetAddress` can only hold a 32-bit host.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Address {
     addr: SocketAddrV4,
 }
 
+/// An error that can occur when converting or resolving an `Address`.
+#[derive(Fail, Debug)]
+pub enum AddressError {
+    /// The address is an IPv6 address, which ENet does not support.
+    #[fail(display = "IPv6 address '{}' is not supported by ENet", _0)]
+    Ipv6NotSupported(SocketAddrV6),
+    /// Resolving the address failed.
+    #[fail(display = "could not resolve address: {}", _0)]
+    Resolve(#[cause] std::io::Error),
+    /// The address was resolved, but has no IPv4 addresses.
+    #[fail(display = "address has no IPv4 addresses")]
+    NoIpv4Address,
+}
+
 impl Address {
+    /// Resolves `addr`, and returns all IPv4 addresses it resolves to.
+    ///
+    /// This may perform a DNS lookup, e.g. for `("example.com", 1234)` or `"example.com:1234"`.
+    /// IPv6 addresses are skipped, and an error is returned if no IPv4 address remains.
+    pub fn resolve<A: ToSocketAddrs>(addr: A) -> Result<Vec<Address>, AddressError> {
+        let addresses: Vec<Address> = addr
+            .to_socket_addrs()
+            .map_err(AddressError::Resolve)?
+            .filter_map(|a| Address::try_from(a).ok())
+            .collect();
+
+        if addresses.is_empty() {
+            return Err(AddressError::NoIpv4Address);
+        }
+
+        Ok(addresses)
+    }
+
     /// Create a new address from an ip and a port.
     pub fn new(addr: Ipv4Addr, port: u16) -> Address {
         Address {
@@ -40,6 +79,41 @@ impl Address {
         ))
     }
 
+    /// Returns the hostname of this address, using a reverse DNS lookup.
+    ///
+    /// If no hostname can be found, ENet falls back to the printable form of the ip.
+    pub fn hostname(&self) -> Result<String, Error> {
+        use enet_sys::enet_address_get_host;
+
+        self.read_host_string(enet_address_get_host)
+    }
+
+    /// Returns the printable form of the ip of this address.
+    pub fn ip_string(&self) -> Result<String, Error> {
+        use enet_sys::enet_address_get_host_ip;
+
+        self.read_host_string(enet_address_get_host_ip)
+    }
+
+    fn read_host_string(
+        &self,
+        get_host: unsafe extern "C" fn(*const ENetAddress, *mut c_char, usize) -> c_int,
+    ) -> Result<String, Error> {
+        // large enough for any hostname (`NI_MAXHOST`)
+        let mut name = [0 as c_char; 1025];
+        let addr = self.to_enet_address();
+
+        let res = unsafe { get_host(&addr as *const _, name.as_mut_ptr(), name.len()) };
+
+        if res != 0 {
+            return Err(Error(res));
+        }
+
+        Ok(unsafe { CStr::from_ptr(name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned())
+    }
+
     /// Return the ip of this address
     pub fn ip(&self) -> &Ipv4Addr {
         self.addr.ip()
@@ -73,12 +147,63 @@ impl Address {
     }
 }
 
+impl From<SocketAddrV4> for Address {
+    fn from(addr: SocketAddrV4) -> Address {
+        Address { addr }
+    }
+}
+
+impl From<Address> for SocketAddrV4 {
+    fn from(addr: Address) -> SocketAddrV4 {
+        addr.addr
+    }
+}
+
+impl From<Address> for SocketAddr {
+    fn from(addr: Address) -> SocketAddr {
+        SocketAddr::V4(addr.addr)
+    }
+}
+
+impl TryFrom<SocketAddr> for Address {
+    type Error = AddressError;
+
+    fn try_from(addr: SocketAddr) -> Result<Address, AddressError> {
+        match addr {
+            SocketAddr::V4(addr) => Ok(Address::from(addr)),
+            SocketAddr::V6(addr) => Err(AddressError::Ipv6NotSupported(addr)),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    /// Parses an address of the form `host:port`.
+    ///
+    /// If `host` is not an IPv4 address, it is resolved (see `Address::resolve`), and the first result is used.
+    fn from_str(s: &str) -> Result<Address, AddressError> {
+        if let Ok(addr) = s.parse::<SocketAddrV4>() {
+            return Ok(Address::from(addr));
+        }
+
+        Ok(Address::resolve(s)?.remove(0))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.addr, f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Address;
+    use super::{Address, AddressError};
 
+    use std::convert::TryFrom;
     use std::ffi::CString;
-    use std::net::Ipv4Addr;
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
     #[test]
     fn test_from_valid_hostname() {
@@ -91,4 +216,50 @@ mod tests {
     fn test_from_invalid_hostname() {
         assert!(Address::from_hostname(&CString::new("").unwrap(), 0).is_err());
     }
+
+    #[test]
+    fn test_std_conversions() {
+        let std_addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1234);
+        let addr = Address::from(std_addr);
+        assert_eq!(addr, Address::new(Ipv4Addr::new(10, 0, 0, 1), 1234));
+        assert_eq!(SocketAddr::from(addr.clone()), SocketAddr::V4(std_addr));
+        assert_eq!(Address::try_from(SocketAddr::V4(std_addr)).unwrap(), addr);
+
+        match Address::try_from("[::1]:1234".parse::<SocketAddr>().unwrap()) {
+            Err(AddressError::Ipv6NotSupported(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_str_and_display() {
+        let addr: Address = "127.0.0.1:9001".parse().unwrap();
+        assert_eq!(addr, Address::new(Ipv4Addr::LOCALHOST, 9001));
+        assert_eq!(addr.to_string(), "127.0.0.1:9001");
+
+        let addr: Address = "localhost:9001".parse().unwrap();
+        assert_eq!(addr.port(), 9001);
+
+        assert!("127.0.0.1".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let addrs = Address::resolve(("127.0.0.1", 80)).unwrap();
+        assert_eq!(addrs, vec![Address::new(Ipv4Addr::LOCALHOST, 80)]);
+
+        assert!(Address::resolve("[::1]:80").is_err());
+    }
+
+    #[test]
+    fn test_ip_string() {
+        let addr = Address::new(Ipv4Addr::new(192, 168, 1, 20), 0);
+        assert_eq!(addr.ip_string().unwrap(), "192.168.1.20");
+    }
+
+    #[test]
+    fn test_hostname() {
+        let addr = Address::new(Ipv4Addr::LOCALHOST, 0);
+        assert!(!addr.hostname().unwrap().is_empty());
+    }
 }
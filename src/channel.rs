@@ -0,0 +1,250 @@
+use std::any::{type_name, TypeId};
+
+use enet_sys::ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT;
+
+use crate::{ChannelLimit, Packet, PacketMode};
+
+/// Declares the channels used by a connection, with a name, a default `PacketMode`
+/// and optionally the type of messages sent on each channel.
+///
+/// Channels are numbered in the order they are declared, starting at 0. Both ends of a connection should
+/// use the same layout, e.g. by passing it to `Enet::create_host_with_layout` and `Host::connect_with_layout`.
+/// Incoming packets can be checked against the layout using `ChannelLayout::validate`.
+///
+/// ```
+/// use enet::{ChannelLayout, PacketMode};
+///
+/// struct ChatMessage;
+///
+/// let layout = ChannelLayout::new()
+///     .channel("movement", PacketMode::UnreliableSequenced)
+///     .message_channel::<ChatMessage, _>("chat", PacketMode::ReliableSequenced);
+///
+/// assert_eq!(layout.channel_count(), 2);
+/// assert_eq!(layout.by_name("chat").unwrap().id(), 1);
+/// assert_eq!(layout.for_message::<ChatMessage>().unwrap().name(), "chat");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChannelLayout {
+    channels: Vec<Channel>,
+}
+
+/// A channel declared in a `ChannelLayout`.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    id: u8,
+    name: String,
+    mode: PacketMode,
+    message_type: Option<(TypeId, &'static str)>,
+}
+
+/// An error that can occur when validating an incoming packet against a `ChannelLayout`.
+#[derive(Fail, Debug)]
+pub enum ChannelError {
+    /// The packet was received on a channel that is not declared in the layout.
+    #[fail(display = "channel {} is not declared", _0)]
+    UndeclaredChannel(u8),
+    /// The packet was delivered with a different reliability or sequencing than declared for its channel.
+    #[fail(
+        display = "packet on channel '{}' was sent as {:?}, expected {:?}",
+        channel, actual, expected
+    )]
+    ModeMismatch {
+        /// The name of the channel.
+        channel: String,
+        /// The mode declared for the channel.
+        expected: PacketMode,
+        /// The mode the packet was delivered with.
+        actual: PacketMode,
+    },
+}
+
+impl ChannelLayout {
+    /// Creates a layout without any channels.
+    pub fn new() -> ChannelLayout {
+        ChannelLayout::default()
+    }
+
+    /// Declares the next channel, which is used with `mode` by default.
+    ///
+    /// Panics if a channel named `name` already exists, or if the maximum number of channels is exceeded.
+    pub fn channel<S: Into<String>>(self, name: S, mode: PacketMode) -> ChannelLayout {
+        self.push(name.into(), mode, None)
+    }
+
+    /// Declares the next channel, which carries messages of type `M` and is used with `mode` by default.
+    ///
+    /// Panics if a channel named `name` already exists, or if the maximum number of channels is exceeded.
+    pub fn message_channel<M: 'static, S: Into<String>>(
+        self,
+        name: S,
+        mode: PacketMode,
+    ) -> ChannelLayout {
+        self.push(
+            name.into(),
+            mode,
+            Some((TypeId::of::<M>(), type_name::<M>())),
+        )
+    }
+
+    fn push(
+        mut self,
+        name: String,
+        mode: PacketMode,
+        message_type: Option<(TypeId, &'static str)>,
+    ) -> ChannelLayout {
+        assert!(
+            self.channels.len() < ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT as usize,
+            "too many channels"
+        );
+        assert!(
+            self.by_name(&name).is_none(),
+            "channel '{}' is declared twice",
+            name
+        );
+
+        self.channels.push(Channel {
+            id: self.channels.len() as u8,
+            name,
+            mode,
+            message_type,
+        });
+
+        self
+    }
+
+    /// Returns the number of declared channels, which can be passed to `Host::connect`.
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Returns the channel limit for hosts using this layout, which is used by `Enet::create_host_with_layout`.
+    ///
+    /// This is at least one channel, since ENet treats a limit of zero as the maximum.
+    pub fn channel_limit(&self) -> ChannelLimit {
+        ChannelLimit::Limited(self.channel_count().max(1))
+    }
+
+    /// Returns all declared channels, ordered by their id.
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    /// Returns the channel with the given id, if it is declared.
+    pub fn get(&self, channel_id: u8) -> Option<&Channel> {
+        self.channels.get(channel_id as usize)
+    }
+
+    /// Returns the channel named `name`, if it is declared.
+    pub fn by_name(&self, name: &str) -> Option<&Channel> {
+        self.channels.iter().find(|c| c.name == name)
+    }
+
+    /// Returns the first channel declared to carry messages of type `M`, if any.
+    pub fn for_message<M: 'static>(&self) -> Option<&Channel> {
+        let type_id = TypeId::of::<M>();
+
+        self.channels
+            .iter()
+            .find(|c| c.message_type.map(|(t, _)| t) == Some(type_id))
+    }
+
+    /// Checks that `packet`, received on `channel_id`, matches this layout.
+    ///
+    /// The channel must be declared, and the packet must have been delivered with the reliability and sequencing
+    /// declared for the channel. Returns the channel on success.
+    pub fn validate(&self, channel_id: u8, packet: &Packet) -> Result<&Channel, ChannelError> {
+        let channel = self
+            .get(channel_id)
+            .ok_or(ChannelError::UndeclaredChannel(channel_id))?;
+
+        let actual = packet.packet_mode();
+        if actual.is_reliable() != channel.mode.is_reliable()
+            || actual.is_sequenced() != channel.mode.is_sequenced()
+        {
+            return Err(ChannelError::ModeMismatch {
+                channel: channel.name.clone(),
+                expected: channel.mode,
+                actual,
+            });
+        }
+
+        Ok(channel)
+    }
+}
+
+impl Channel {
+    /// Returns the id of this channel, as used by `Peer::send_packet` and `Event::Receive`.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Returns the name of this channel.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the default `PacketMode` of this channel.
+    pub fn mode(&self) -> PacketMode {
+        self.mode
+    }
+
+    /// Returns the name of the message type declared for this channel, if any.
+    pub fn message_type_name(&self) -> Option<&'static str> {
+        self.message_type.map(|(_, name)| name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelError, ChannelLayout};
+    use crate::{Packet, PacketMode};
+
+    #[test]
+    fn test_layout() {
+        let layout = ChannelLayout::new()
+            .channel("input", PacketMode::UnreliableUnsequenced)
+            .message_channel::<String, _>("chat", PacketMode::ReliableSequenced)
+            .channel("snapshots", PacketMode::UnreliableFragmented);
+
+        assert_eq!(layout.channel_count(), 3);
+        assert_eq!(layout.get(1).unwrap().name(), "chat");
+        assert_eq!(layout.by_name("snapshots").unwrap().id(), 2);
+        assert_eq!(layout.for_message::<String>().unwrap().id(), 1);
+        assert!(layout.for_message::<u32>().is_none());
+        assert!(layout.get(3).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_duplicate_channel() {
+        ChannelLayout::new()
+            .channel("chat", PacketMode::ReliableSequenced)
+            .channel("chat", PacketMode::UnreliableSequenced);
+    }
+
+    #[test]
+    fn test_validate() {
+        let layout = ChannelLayout::new()
+            .channel("chat", PacketMode::ReliableSequenced)
+            .channel("snapshots", PacketMode::UnreliableFragmented);
+
+        let reliable = Packet::new(b"", PacketMode::ReliableSequenced).unwrap();
+        let unreliable = Packet::new(b"", PacketMode::UnreliableSequenced).unwrap();
+
+        assert_eq!(layout.validate(0, &reliable).unwrap().name(), "chat");
+        // small fragmented packets arrive as plain unreliable packets
+        assert_eq!(layout.validate(1, &unreliable).unwrap().name(), "snapshots");
+
+        match layout.validate(0, &unreliable) {
+            Err(ChannelError::ModeMismatch { expected, .. }) => {
+                assert_eq!(expected, PacketMode::ReliableSequenced)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match layout.validate(2, &reliable) {
+            Err(ChannelError::UndeclaredChannel(2)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
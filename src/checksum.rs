@@ -0,0 +1,80 @@
+use std::cell::Cell;
+
+use enet_sys::{enet_crc32, ENetBuffer, ENetChecksumCallback};
+
+use crate::compressor::buffers_as_slices;
+
+pub(crate) type ChecksumFn = dyn Fn(&[&[u8]]) -> u32 + Send;
+
+thread_local! {
+    /// The custom checksum of the `Host` that is currently calling into ENet on this thread, if any.
+    static ACTIVE_CHECKSUM: Cell<Option<*const ChecksumFn>> = Cell::new(None);
+}
+
+/// The checksum used by a `Host` to verify the integrity of its datagrams.
+///
+/// Both ends of a connection must use the same checksum, otherwise no connection can be established.
+pub enum ChecksumKind {
+    /// Datagrams are not checksummed (ENet default).
+    None,
+    /// Datagrams are checksummed using ENet's built-in CRC32 implementation.
+    Crc32,
+    /// Datagrams are checksummed using a user-provided function, which receives the datagram as a list of buffers.
+    ///
+    /// The function is called on the thread that calls into ENet, whenever ENet sends or receives a datagram.
+    Custom(Box<dyn Fn(&[&[u8]]) -> u32 + Send>),
+}
+
+impl std::fmt::Debug for ChecksumKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumKind::None => write!(f, "None"),
+            ChecksumKind::Crc32 => write!(f, "Crc32"),
+            ChecksumKind::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+impl ChecksumKind {
+    /// Splits this checksum into the callback to install on the `ENetHost`, and the custom function (if any)
+    /// that has to be activated whenever the `Host` calls into ENet.
+    pub(crate) fn into_sys_checksum(self) -> (ENetChecksumCallback, Option<Box<ChecksumFn>>) {
+        match self {
+            ChecksumKind::None => (None, None),
+            ChecksumKind::Crc32 => (Some(enet_crc32), None),
+            ChecksumKind::Custom(f) => (Some(custom_checksum_trampoline), Some(f)),
+        }
+    }
+}
+
+/// Makes a custom checksum available to ENet on this thread, until the returned guard is dropped.
+pub(crate) struct ActiveChecksumGuard {
+    previous: Option<*const ChecksumFn>,
+}
+
+impl ActiveChecksumGuard {
+    pub(crate) fn new(checksum: Option<&ChecksumFn>) -> ActiveChecksumGuard {
+        let previous =
+            ACTIVE_CHECKSUM.with(|active| active.replace(checksum.map(|f| f as *const _)));
+
+        ActiveChecksumGuard { previous }
+    }
+}
+
+impl Drop for ActiveChecksumGuard {
+    fn drop(&mut self) {
+        ACTIVE_CHECKSUM.with(|active| active.set(self.previous));
+    }
+}
+
+unsafe extern "C" fn custom_checksum_trampoline(
+    buffers: *const ENetBuffer,
+    buffer_count: usize,
+) -> u32 {
+    let buffers = buffers_as_slices(buffers, buffer_count);
+
+    match ACTIVE_CHECKSUM.with(Cell::get) {
+        Some(f) => (*f)(&buffers),
+        None => 0,
+    }
+}
@@ -0,0 +1,167 @@
+use std::os::raw::c_void;
+
+use enet_sys::{
+    enet_range_coder_compress, enet_range_coder_create, enet_range_coder_decompress,
+    enet_range_coder_destroy, ENetBuffer, ENetCompressor,
+};
+
+/// A custom compression scheme that can be installed on a `Host` using `Host::set_compressor`.
+///
+/// ENet compresses whole datagrams, which are handed to the compressor as a list of buffers.
+/// Both ends of a connection must use the same compression, otherwise datagrams will be dropped.
+pub trait Compressor {
+    /// Compresses the concatenation of `in_buffers` into `out`.
+    ///
+    /// `in_limit` is the total length of all buffers in `in_buffers`.
+    /// Returns the number of bytes written to `out`, or `None` if the data could not be compressed into `out`.
+    /// In that case, ENet sends the datagram uncompressed.
+    fn compress(&mut self, in_buffers: &[&[u8]], in_limit: usize, out: &mut [u8]) -> Option<usize>;
+
+    /// Decompresses `input` into `out`.
+    ///
+    /// Returns the number of bytes written to `out`, or `None` if decompression failed.
+    /// In that case, ENet drops the datagram.
+    fn decompress(&mut self, input: &[u8], out: &mut [u8]) -> Option<usize>;
+}
+
+/// ENet's built-in range coder, as a `Compressor`.
+///
+/// Hosts using `Host::enable_range_coder_compression` compress their datagrams with this coder. A `RangeCoder` can
+/// e.g. be passed to `protocol::Dissector::decompressor` to read captured datagrams of such hosts.
+#[derive(Debug)]
+pub struct RangeCoder {
+    context: *mut c_void,
+}
+
+// The context only holds the coder's state, which is not shared with anything else.
+unsafe impl Send for RangeCoder {}
+
+impl RangeCoder {
+    /// Creates a new range coder.
+    pub fn new() -> RangeCoder {
+        let context = unsafe { enet_range_coder_create() };
+        assert!(!context.is_null(), "could not allocate range coder");
+
+        RangeCoder { context }
+    }
+}
+
+impl Default for RangeCoder {
+    fn default() -> RangeCoder {
+        RangeCoder::new()
+    }
+}
+
+impl Compressor for RangeCoder {
+    fn compress(&mut self, in_buffers: &[&[u8]], in_limit: usize, out: &mut [u8]) -> Option<usize> {
+        let buffers: Vec<ENetBuffer> = in_buffers
+            .iter()
+            .map(|b| ENetBuffer {
+                data: b.as_ptr() as *mut c_void,
+                dataLength: b.len(),
+            })
+            .collect();
+
+        let len = unsafe {
+            enet_range_coder_compress(
+                self.context,
+                buffers.as_ptr(),
+                buffers.len(),
+                in_limit,
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+
+        Some(len).filter(|&len| len > 0)
+    }
+
+    fn decompress(&mut self, input: &[u8], out: &mut [u8]) -> Option<usize> {
+        let len = unsafe {
+            enet_range_coder_decompress(
+                self.context,
+                input.as_ptr(),
+                input.len(),
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+
+        Some(len).filter(|&len| len > 0)
+    }
+}
+
+impl Drop for RangeCoder {
+    fn drop(&mut self) {
+        unsafe {
+            enet_range_coder_destroy(self.context);
+        }
+    }
+}
+
+/// Moves `compressor` into an `ENetCompressor`, which is freed by ENet through its `destroy` callback.
+pub(crate) fn to_sys_compressor(compressor: Box<dyn Compressor + Send>) -> ENetCompressor {
+    let context: *mut Box<dyn Compressor + Send> = Box::into_raw(Box::new(compressor));
+
+    ENetCompressor {
+        context: context as *mut c_void,
+        compress: Some(compress_trampoline),
+        decompress: Some(decompress_trampoline),
+        destroy: Some(destroy_trampoline),
+    }
+}
+
+/// Returns the contents of the given `ENetBuffer`s as a list of slices.
+pub(crate) unsafe fn buffers_as_slices<'a>(
+    buffers: *const ENetBuffer,
+    buffer_count: usize,
+) -> Vec<&'a [u8]> {
+    if buffers.is_null() {
+        return Vec::new();
+    }
+
+    std::slice::from_raw_parts(buffers, buffer_count)
+        .iter()
+        .map(|b| std::slice::from_raw_parts(b.data as *const u8, b.dataLength))
+        .collect()
+}
+
+unsafe extern "C" fn compress_trampoline(
+    context: *mut c_void,
+    in_buffers: *const ENetBuffer,
+    in_buffer_count: usize,
+    in_limit: usize,
+    out_data: *mut u8,
+    out_limit: usize,
+) -> usize {
+    let compressor = &mut *(context as *mut Box<dyn Compressor + Send>);
+    let in_buffers = buffers_as_slices(in_buffers, in_buffer_count);
+    let out = std::slice::from_raw_parts_mut(out_data, out_limit);
+
+    match compressor.compress(&in_buffers, in_limit, out) {
+        Some(len) if len <= out_limit => len,
+        _ => 0,
+    }
+}
+
+unsafe extern "C" fn decompress_trampoline(
+    context: *mut c_void,
+    in_data: *const u8,
+    in_limit: usize,
+    out_data: *mut u8,
+    out_limit: usize,
+) -> usize {
+    let compressor = &mut *(context as *mut Box<dyn Compressor + Send>);
+    let input = std::slice::from_raw_parts(in_data, in_limit);
+    let out = std::slice::from_raw_parts_mut(out_data, out_limit);
+
+    match compressor.decompress(input, out) {
+        Some(len) if len <= out_limit => len,
+        _ => 0,
+    }
+}
+
+unsafe extern "C" fn destroy_trampoline(context: *mut c_void) {
+    let _: Box<Box<dyn Compressor + Send>> =
+        Box::from_raw(context as *mut Box<dyn Compressor + Send>);
+}
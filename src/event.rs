@@ -3,24 +3,44 @@ use enet_sys::{
     _ENetEventType_ENET_EVENT_TYPE_NONE, _ENetEventType_ENET_EVENT_TYPE_RECEIVE,
 };
 
-use crate::{Host, Packet, Peer};
+use crate::checksum::ChecksumFn;
+use crate::{Address, Packet, Peer, PeerId};
 
 /// This enum represents an event that can occur when servicing an `EnetHost`.
 ///
 /// Also see the official ENet documentation for more information.
 #[derive(Debug)]
 pub enum Event<'a, T> {
-    /// This variant represents the connection of a peer, contained in the only field.
-    Connect(Peer<'a, T>, usize),
+    /// This variant represents the connection of a peer.
+    ///
+    /// The connected peer is contained in the first field, and its `PeerId` in the second field.
+    /// The third field contains the user-specified data the remote side passed to `Host::connect`,
+    /// which is always 0 on the connecting side.
+    ///
+    /// Servers can reject connections based on this data by disconnecting the peer right away:
+    ///
+    /// ```no_run
+    /// # fn handle(mut event: enet::Event<'_, ()>) {
+    /// # const PROTOCOL_VERSION: u32 = 1;
+    /// if let enet::Event::Connect(ref mut peer, _, version) = event {
+    ///     if version != PROTOCOL_VERSION {
+    ///         peer.disconnect(PROTOCOL_VERSION);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    Connect(Peer<'a, T>, PeerId, u32),
     /// This variant represents the disconnection of a peer, either because it was requested or due to a timeout.
     ///
-    /// The disconnected peer is contained in the first field, while the second field contains the user-specified
-    /// data for this disconnection.
-    Disconnect(Peer<'a, T>, u32),
+    /// The disconnected peer is contained in the first field, and the `PeerId` of the ended connection in the
+    /// second field. The third field contains the user-specified data for this disconnection.
+    Disconnect(Peer<'a, T>, PeerId, u32),
     /// This variants repersents a packet that was received.
     Receive {
         /// The `Peer` that sent the packet.
         sender: Peer<'a, T>,
+        /// The `PeerId` of the `Peer` that sent the packet.
+        sender_id: PeerId,
         /// The channel on which the packet was received.
         channel_id: u8,
         /// The `Packet` that was received.
@@ -28,29 +48,135 @@ pub enum Event<'a, T> {
     },
 }
 
+/// An event that does not borrow the `Host` it occurred on, so it can be stored or sent to other threads.
+///
+/// Created through `Event::into_owned`, or delivered by the service thread of a `Host` (see `Host::spawn_thread`).
+#[derive(Debug)]
+pub enum OwnedEvent {
+    /// A peer connected, see `Event::Connect`.
+    Connect {
+        /// The `PeerId` of the new connection.
+        peer_id: PeerId,
+        /// The address of the peer.
+        address: Address,
+        /// The user-specified data the remote side passed to `Host::connect`.
+        data: u32,
+    },
+    /// A peer disconnected, see `Event::Disconnect`.
+    Disconnect {
+        /// The `PeerId` of the ended connection.
+        peer_id: PeerId,
+        /// The address of the peer.
+        address: Address,
+        /// The user-specified data for this disconnection.
+        data: u32,
+    },
+    /// A packet was received, see `Event::Receive`.
+    Receive {
+        /// The `PeerId` of the peer that sent the packet.
+        peer_id: PeerId,
+        /// The address of the peer that sent the packet.
+        address: Address,
+        /// The channel on which the packet was received.
+        channel_id: u8,
+        /// The `Packet` that was received.
+        packet: Packet,
+    },
+}
+
+impl OwnedEvent {
+    /// Returns the `PeerId` of the peer this event belongs to.
+    pub fn peer_id(&self) -> PeerId {
+        match self {
+            OwnedEvent::Connect { peer_id, .. } => *peer_id,
+            OwnedEvent::Disconnect { peer_id, .. } => *peer_id,
+            OwnedEvent::Receive { peer_id, .. } => *peer_id,
+        }
+    }
+
+    /// Returns the address of the peer this event belongs to.
+    pub fn address(&self) -> &Address {
+        match self {
+            OwnedEvent::Connect { address, .. } => address,
+            OwnedEvent::Disconnect { address, .. } => address,
+            OwnedEvent::Receive { address, .. } => address,
+        }
+    }
+}
+
 impl<'a, T> Event<'a, T> {
-    pub(crate) fn from_sys_event<'b>(
-        host: &Host<T>,
-        event_sys: &'b ENetEvent,
+    pub(crate) fn from_sys_event(
+        event_sys: &ENetEvent,
+        peer_id: PeerId,
+        checksum: Option<*const ChecksumFn>,
     ) -> Option<Event<'a, T>> {
         #[allow(non_upper_case_globals)]
         match event_sys.type_ {
             _ENetEventType_ENET_EVENT_TYPE_NONE => None,
-            _ENetEventType_ENET_EVENT_TYPE_CONNECT => {
-                let peer_index = host.peers().position(|a| a == event_sys.peer).unwrap();
-                Some(Event::Connect(Peer::new(event_sys.peer), peer_index))
-            }
-            _ENetEventType_ENET_EVENT_TYPE_DISCONNECT => {
-                Some(Event::Disconnect(Peer::new(event_sys.peer), event_sys.data))
-            }
+            _ENetEventType_ENET_EVENT_TYPE_CONNECT => Some(Event::Connect(
+                Peer::new(event_sys.peer, checksum),
+                peer_id,
+                event_sys.data,
+            )),
+            _ENetEventType_ENET_EVENT_TYPE_DISCONNECT => Some(Event::Disconnect(
+                Peer::new(event_sys.peer, checksum),
+                peer_id,
+                event_sys.data,
+            )),
             _ENetEventType_ENET_EVENT_TYPE_RECEIVE => Some(Event::Receive {
-                sender: Peer::new(event_sys.peer),
+                sender: Peer::new(event_sys.peer, checksum),
+                sender_id: peer_id,
                 channel_id: event_sys.channelID,
                 packet: Packet::from_sys_packet(event_sys.packet),
             }),
             _ => panic!("unrecognized event type: {}", event_sys.type_),
         }
     }
+
+    /// Returns the `PeerId` of the peer this event belongs to.
+    pub fn peer_id(&self) -> PeerId {
+        match self {
+            Event::Connect(_, id, _) => *id,
+            Event::Disconnect(_, id, _) => *id,
+            Event::Receive { sender_id, .. } => *sender_id,
+        }
+    }
+
+    /// Converts this event into an `OwnedEvent`, which no longer borrows the `Host`.
+    ///
+    /// This allows events to be collected while servicing, and processed once the `Host` can be used again.
+    pub fn into_owned(self) -> OwnedEvent {
+        match self {
+            Event::Connect(ref peer, peer_id, data) => OwnedEvent::Connect {
+                peer_id,
+                address: peer.address(),
+                data,
+            },
+            Event::Disconnect(ref peer, peer_id, data) => OwnedEvent::Disconnect {
+                peer_id,
+                address: peer.address(),
+                data,
+            },
+            Event::Receive {
+                ref sender,
+                sender_id,
+                channel_id,
+                ref packet,
+            } => {
+                let owned = OwnedEvent::Receive {
+                    peer_id: sender_id,
+                    address: sender.address(),
+                    channel_id,
+                    packet: unsafe { std::ptr::read(packet) },
+                };
+
+                // the packet now belongs to `owned`, and dropping a `Receive` event does nothing else
+                std::mem::forget(self);
+
+                owned
+            }
+        }
+    }
 }
 
 impl<'a, T> Drop for Event<'a, T> {
@@ -60,7 +186,7 @@ impl<'a, T> Drop for Event<'a, T> {
             // However, this is *not really clear* in the ENet docs!
             // It looks like the Peer *might* live longer, but not shorter, so it should be safe
             // to destroy the associated data (if any) here.
-            Event::Disconnect(peer, _) => peer.set_data(None),
+            Event::Disconnect(peer, ..) => peer.set_data(None),
             _ => (),
         }
     }
@@ -0,0 +1,231 @@
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::{Address, Error, Host, OwnedEvent, Packet, PeerId};
+
+/// How long the service thread waits for network events, before it checks for new commands.
+const SERVICE_TIMEOUT_MS: u32 = 5;
+
+/// An operation queued by a `HostHandle`, which is executed on the service thread.
+enum Command {
+    Send {
+        peer_id: PeerId,
+        channel_id: u8,
+        packet: Packet,
+        reply: Sender<Result<(), Error>>,
+    },
+    Broadcast {
+        channel_id: u8,
+        packet: Packet,
+    },
+    Disconnect {
+        peer_id: PeerId,
+        user_data: u32,
+    },
+    Connect {
+        address: Address,
+        channel_count: usize,
+        user_data: u32,
+        reply: Sender<Result<PeerId, Error>>,
+    },
+}
+
+/// An error that can occur when using a `HostHandle`.
+#[derive(Fail, Debug)]
+pub enum HandleError {
+    /// The service thread has stopped, e.g. because it panicked.
+    #[fail(display = "the service thread of the host has stopped")]
+    HostStopped,
+    /// Servicing the `Host` failed, which stopped the service thread.
+    #[fail(display = "servicing the host failed: {}", _0)]
+    ServiceFailed(#[cause] Error),
+    /// ENet failed to execute the command.
+    #[fail(display = "{}", _0)]
+    Enet(#[cause] Error),
+}
+
+/// A handle to a `Host` that is serviced on its own thread, which can be cloned and sent between threads.
+///
+/// Created through `Host::spawn_thread`. The service thread stops, and the `Host` is destroyed,
+/// once all handles have been dropped, or if servicing the `Host` fails.
+#[derive(Clone, Debug)]
+pub struct HostHandle {
+    commands: Sender<Command>,
+    /// The error that stopped the service thread, if any.
+    failure: Arc<Mutex<Option<Error>>>,
+}
+
+/// Moves a `Host` to its service thread.
+struct SendHost<T>(Host<T>);
+
+// A `Host` is the only owner of its `ENetHost`, and all callbacks it holds (compressor, checksum, intercept)
+// are required to be `Send`. The data of its peers is only accessed on the service thread.
+unsafe impl<T: Send> Send for SendHost<T> {}
+
+impl<T: Send + 'static> Host<T> {
+    /// Moves this `Host` to a dedicated thread, which services it continuously.
+    ///
+    /// Returns a `HostHandle` to send packets and manage connections from any thread,
+    /// and a `Receiver` that delivers all events of this `Host`. Commands are executed within a few
+    /// milliseconds, as the service thread checks for them between services.
+    ///
+    /// If servicing the `Host` fails, the service thread stops: the `Receiver` disconnects once all events
+    /// have been received, and the `HostHandle` returns `HandleError::ServiceFailed`.
+    pub fn spawn_thread(self) -> (HostHandle, Receiver<OwnedEvent>) {
+        let (command_sender, commands) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+        let failure = Arc::new(Mutex::new(None));
+        let host = SendHost(self);
+
+        let thread_failure = failure.clone();
+        thread::spawn(move || {
+            let SendHost(host) = host;
+            if let Err(err) = run_service_thread(host, commands, event_sender) {
+                *thread_failure.lock().unwrap() = Some(err);
+            }
+        });
+
+        (
+            HostHandle {
+                commands: command_sender,
+                failure,
+            },
+            events,
+        )
+    }
+}
+
+/// Services `host` until all handles are dropped, or servicing fails.
+fn run_service_thread<T>(
+    mut host: Host<T>,
+    commands: Receiver<Command>,
+    events: Sender<OwnedEvent>,
+) -> Result<(), Error> {
+    let mut batch = Vec::new();
+
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(command) => execute(&mut host, command),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        let res = host.service_batch(SERVICE_TIMEOUT_MS, &mut batch);
+
+        for event in batch.drain(..) {
+            // events are dropped if nobody listens for them anymore
+            let _ = events.send(event);
+        }
+        res?;
+    }
+}
+
+/// Executes `command` on `host`. Commands for peers that are not connected anymore are ignored.
+fn execute<T>(host: &mut Host<T>, command: Command) {
+    match command {
+        Command::Send {
+            peer_id,
+            channel_id,
+            packet,
+            reply,
+        } => {
+            let res = match host.peer(peer_id) {
+                Some(mut peer) => peer.send_packet(packet, channel_id),
+                None => Ok(()),
+            };
+            let _ = reply.send(res);
+        }
+        Command::Broadcast { channel_id, packet } => host.broadcast(packet, channel_id),
+        Command::Disconnect { peer_id, user_data } => {
+            if let Some(mut peer) = host.peer(peer_id) {
+                peer.disconnect(user_data);
+            }
+        }
+        Command::Connect {
+            address,
+            channel_count,
+            user_data,
+            reply,
+        } => {
+            let res = host
+                .connect(&address, channel_count, user_data)
+                .map(|peer| peer.id());
+            let _ = reply.send(res);
+        }
+    }
+}
+
+impl HostHandle {
+    /// Queues `packet` to be sent to the peer identified by `peer_id` on `channel_id`.
+    ///
+    /// Blocks until the service thread has queued the packet. The packet is dropped if the peer is not connected
+    /// anymore when the service thread processes it.
+    pub fn send(&self, peer_id: PeerId, channel_id: u8, packet: Packet) -> Result<(), HandleError> {
+        let (reply, response) = mpsc::channel();
+
+        self.queue(Command::Send {
+            peer_id,
+            channel_id,
+            packet,
+            reply,
+        })?;
+
+        self.wait(response)
+    }
+
+    /// Queues `packet` to be sent to all connected peers on `channel_id`, see `Host::broadcast`.
+    pub fn broadcast(&self, packet: Packet, channel_id: u8) -> Result<(), HandleError> {
+        self.queue(Command::Broadcast { channel_id, packet })
+    }
+
+    /// Requests a disconnection from the peer identified by `peer_id`, see `Peer::disconnect`.
+    pub fn disconnect(&self, peer_id: PeerId, user_data: u32) -> Result<(), HandleError> {
+        self.queue(Command::Disconnect { peer_id, user_data })
+    }
+
+    /// Initiates a connection to a foreign host, see `Host::connect`.
+    ///
+    /// Blocks until the service thread has started connecting, and returns the `PeerId` of the new connection.
+    pub fn connect(
+        &self,
+        address: &Address,
+        channel_count: usize,
+        user_data: u32,
+    ) -> Result<PeerId, HandleError> {
+        let (reply, response) = mpsc::channel();
+
+        self.queue(Command::Connect {
+            address: address.clone(),
+            channel_count,
+            user_data,
+            reply,
+        })?;
+
+        self.wait(response)
+    }
+
+    fn queue(&self, command: Command) -> Result<(), HandleError> {
+        self.commands
+            .send(command)
+            .map_err(|_| self.stopped_error())
+    }
+
+    /// Waits for the result of a command.
+    fn wait<R>(&self, response: Receiver<Result<R, Error>>) -> Result<R, HandleError> {
+        response
+            .recv()
+            .map_err(|_| self.stopped_error())?
+            .map_err(HandleError::Enet)
+    }
+
+    /// Returns why the service thread has stopped.
+    fn stopped_error(&self) -> HandleError {
+        match *self.failure.lock().unwrap() {
+            Some(Error(code)) => HandleError::ServiceFailed(Error(code)),
+            None => HandleError::HostStopped,
+        }
+    }
+}
@@ -1,12 +1,22 @@
 use std::marker::PhantomData;
 use std::sync::Arc;
 
-use crate::{Address, EnetKeepAlive, Error, Event, Peer};
+use crate::checksum::{ActiveChecksumGuard, ChecksumFn};
+use crate::compressor::to_sys_compressor;
+use crate::intercept::{intercept_trampoline, ActiveInterceptGuard, InterceptFn};
+use crate::record::{ActiveRecorderGuard, Recorder};
+use crate::simulator::ActiveSimulatorGuard;
+use crate::{
+    Address, ChannelLayout, ChecksumKind, Compressor, EnetKeepAlive, Error, Event, InterceptAction,
+    NetworkSimulator, OwnedEvent, Packet, Peer, PeerId, PeerState, RawSocket,
+};
 
 use enet_sys::{
-    enet_host_bandwidth_limit, enet_host_channel_limit, enet_host_check_events, enet_host_connect,
-    enet_host_destroy, enet_host_flush, enet_host_service, ENetEvent, ENetHost, ENetPeer,
-    ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT,
+    enet_host_bandwidth_limit, enet_host_broadcast, enet_host_channel_limit,
+    enet_host_check_events, enet_host_compress, enet_host_compress_with_range_coder,
+    enet_host_connect, enet_host_destroy, enet_host_flush, enet_host_service, enet_packet_destroy,
+    enet_peer_send, ENetEvent, ENetHost, ENetPeer, ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT,
+    _ENetEventType_ENET_EVENT_TYPE_CONNECT, _ENetEventType_ENET_EVENT_TYPE_DISCONNECT,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -62,11 +72,32 @@ impl BandwidthLimit {
     }
 }
 
+/// Counters of the traffic sent and received by a `Host`.
+///
+/// Returned by `Host::traffic_stats` and `Host::take_traffic_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TrafficStats {
+    /// Total data sent, in bytes.
+    pub sent_data: u32,
+    /// Total number of UDP datagrams sent.
+    pub sent_packets: u32,
+    /// Total data received, in bytes.
+    pub received_data: u32,
+    /// Total number of UDP datagrams received.
+    pub received_packets: u32,
+}
+
 /// A `Host` represents one endpoint of an ENet connection. Created through `Enet`.
 ///
 /// This type provides functionality such as connection establishment and packet transmission.
 pub struct Host<T> {
     inner: *mut ENetHost,
+    checksum: Option<Box<ChecksumFn>>,
+    intercept: Option<Box<InterceptFn>>,
+    simulator: Option<NetworkSimulator>,
+    recorder: Option<Recorder>,
+    /// The connect ids of the connections on each peer, as ENet resets them before reporting a disconnection.
+    connect_ids: Vec<u32>,
 
     _keep_alive: Arc<EnetKeepAlive>,
     _peer_data: PhantomData<*const T>,
@@ -76,8 +107,15 @@ impl<T> Host<T> {
     pub(in crate) fn new(_keep_alive: Arc<EnetKeepAlive>, inner: *mut ENetHost) -> Host<T> {
         assert!(!inner.is_null());
 
+        let peer_count = unsafe { (*inner).peerCount };
+
         Host {
             inner,
+            checksum: None,
+            intercept: None,
+            simulator: None,
+            recorder: None,
+            connect_ids: vec![0; peer_count],
             _keep_alive,
             _peer_data: PhantomData,
         }
@@ -87,6 +125,8 @@ impl<T> Host<T> {
     ///
     /// This function need only be used in circumstances where one wishes to send queued packets earlier than in a call to `Host::service()`.
     pub fn flush(&mut self) {
+        let _checksum = self.activate_checksum();
+
         unsafe {
             enet_host_flush(self.inner);
         }
@@ -107,6 +147,184 @@ impl<T> Host<T> {
         }
     }
 
+    /// Enables ENet's built-in range coder compression for this `Host`, replacing any previous compression.
+    ///
+    /// All peers of this `Host` must use the same compression, otherwise packets will be dropped.
+    pub fn enable_range_coder_compression(&mut self) -> Result<(), Error> {
+        let res = unsafe { enet_host_compress_with_range_coder(self.inner) };
+
+        match res {
+            0 => Ok(()),
+            r => Err(Error(r)),
+        }
+    }
+
+    /// Installs a custom `Compressor` for this `Host`, replacing any previous compression.
+    ///
+    /// All peers of this `Host` must use the same compression, otherwise packets will be dropped.
+    pub fn set_compressor<C: Compressor + Send + 'static>(&mut self, compressor: C) {
+        let sys_compressor = to_sys_compressor(Box::new(compressor));
+
+        // ENet copies the compressor and takes care of destroying it (and any previous one).
+        unsafe {
+            enet_host_compress(self.inner, &sys_compressor as *const _);
+        }
+    }
+
+    /// Disables compression for this `Host`.
+    pub fn disable_compression(&mut self) {
+        unsafe {
+            enet_host_compress(self.inner, std::ptr::null());
+        }
+    }
+
+    /// Sets the checksum used to verify the integrity of datagrams sent and received by this `Host`.
+    ///
+    /// All peers of this `Host` must use the same checksum, otherwise no connection can be established.
+    pub fn set_checksum(&mut self, checksum: ChecksumKind) {
+        let (callback, custom) = checksum.into_sys_checksum();
+
+        unsafe {
+            (*self.inner).checksum = callback;
+        }
+        self.checksum = custom;
+    }
+
+    fn activate_checksum(&self) -> ActiveChecksumGuard {
+        ActiveChecksumGuard::new(self.checksum.as_ref().map(|f| &**f))
+    }
+
+    /// Returns the custom checksum of this `Host`, which its `Peer`s activate when they send datagrams.
+    fn custom_checksum(&self) -> Option<*const ChecksumFn> {
+        self.checksum.as_ref().map(|f| &**f as *const _)
+    }
+
+    /// Installs a hook that inspects every datagram received by this `Host`, before it is processed by ENet.
+    ///
+    /// The hook receives the sender's address and the raw datagram, and decides whether ENet should process it.
+    /// This allows other protocols to share the socket of this `Host`. Replies can be sent from inside the hook
+    /// using a `RawSocket`, see `Host::raw_socket`. The hook only runs during `Host::service`.
+    pub fn set_intercept<F>(&mut self, intercept: F)
+    where
+        F: FnMut(&Address, &[u8]) -> InterceptAction + Send + 'static,
+    {
+        self.intercept = Some(Box::new(intercept));
+        self.update_intercept_callback();
+    }
+
+    /// Removes the intercept hook of this `Host`, if any.
+    pub fn clear_intercept(&mut self) {
+        self.intercept = None;
+        self.update_intercept_callback();
+    }
+
+    /// Installs a `NetworkSimulator`, which applies bad network conditions to all datagrams received by this `Host`.
+    ///
+    /// The simulator runs before the intercept hook, which only sees the datagrams the simulator delivers.
+    pub fn set_network_simulator(&mut self, simulator: NetworkSimulator) {
+        self.simulator = Some(simulator);
+        self.update_intercept_callback();
+    }
+
+    /// Removes the `NetworkSimulator` of this `Host`, and returns it. Datagrams it still delays are dropped.
+    pub fn clear_network_simulator(&mut self) -> Option<NetworkSimulator> {
+        let simulator = self.simulator.take();
+        self.update_intercept_callback();
+
+        simulator
+    }
+
+    /// Returns the `NetworkSimulator` of this `Host`, if any, e.g. to change its conditions.
+    pub fn network_simulator_mut(&mut self) -> Option<&mut NetworkSimulator> {
+        self.simulator.as_mut()
+    }
+
+    /// Installs a `Recorder`, which records the datagrams received by this `Host` and its events.
+    ///
+    /// Datagrams are recorded as ENet sees them, i.e. after the `NetworkSimulator` but before the intercept hook.
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+        self.update_intercept_callback();
+    }
+
+    /// Removes the `Recorder` of this `Host`, and returns it so it can be finished.
+    pub fn take_recorder(&mut self) -> Option<Recorder> {
+        let recorder = self.recorder.take();
+        self.update_intercept_callback();
+
+        recorder
+    }
+
+    /// ENet only needs to call the intercept trampoline if there is an intercept hook, a simulator or a recorder.
+    fn update_intercept_callback(&mut self) {
+        let callback =
+            if self.intercept.is_some() || self.simulator.is_some() || self.recorder.is_some() {
+                Some(intercept_trampoline as _)
+            } else {
+                None
+            };
+
+        unsafe {
+            (*self.inner).intercept = callback;
+        }
+    }
+
+    /// Returns the raw file descriptor of the socket of this `Host`.
+    #[cfg(all(feature = "tokio", unix))]
+    pub(crate) fn socket_fd(&self) -> std::os::unix::io::RawFd {
+        unsafe { (*self.inner).socket }
+    }
+
+    /// Returns in how many milliseconds ENet's next timer fires, or `None` if no timer is pending.
+    ///
+    /// Outside of servicing, ENet only acts on its timers: it resends unacknowledged reliable commands,
+    /// pings idle peers and recalculates bandwidth limits. Everything else is triggered by incoming datagrams.
+    #[cfg(all(feature = "tokio", unix))]
+    pub(crate) fn next_timer_ms(&self) -> Option<u32> {
+        /// How often ENet recalculates the bandwidth limits of its peers (`ENET_HOST_BANDWIDTH_THROTTLE_INTERVAL`).
+        const BANDWIDTH_THROTTLE_INTERVAL_MS: u32 = 1000;
+
+        let host = unsafe { &*self.inner };
+        let peers = unsafe { std::slice::from_raw_parts(host.peers, host.peerCount) };
+
+        // timers are relative to the last service; those not after it belong to commands that are gone
+        let mut next: Option<u32> = None;
+        let mut add_timer = |time: u32| {
+            let delay = time.wrapping_sub(host.serviceTime);
+            if (delay as i32) > 0 {
+                next = Some(next.map_or(delay, |next| next.min(delay)));
+            }
+        };
+
+        for peer in peers {
+            match PeerState::from_sys_state(peer.state) {
+                PeerState::Disconnected | PeerState::Zombie => continue,
+                PeerState::Connected | PeerState::DisconnectLater => {
+                    add_timer(peer.lastReceiveTime.wrapping_add(peer.pingInterval));
+                }
+                _ => {}
+            }
+
+            if peer.nextTimeout != 0 {
+                add_timer(peer.nextTimeout);
+            }
+        }
+        if host.connectedPeers > 0 {
+            add_timer(
+                host.bandwidthThrottleEpoch
+                    .wrapping_add(BANDWIDTH_THROTTLE_INTERVAL_MS),
+            );
+        }
+
+        let elapsed = crate::time::now().wrapping_sub(host.serviceTime);
+        next.map(|next| next.saturating_sub(elapsed))
+    }
+
+    /// Returns a handle to the socket of this `Host`, which can be used to send raw datagrams from inside its intercept hook.
+    pub fn raw_socket(&self) -> RawSocket {
+        RawSocket::new(self.inner)
+    }
+
     /// Sets the maximum allowed channels of future connections.
     pub fn set_channel_limit(&mut self, max_channel_count: ChannelLimit) {
         unsafe {
@@ -130,21 +348,69 @@ impl<T> Host<T> {
     }
 
     /// Returns the internet address of this `Host`.
+    ///
+    /// If the `Host` was created with port 0, this contains the port chosen by the operating system.
+    /// This allows tests to listen on a free port, instead of a fixed one.
     pub fn address(&self) -> Address {
         Address::from_enet_address(&unsafe { (*self.inner).address })
     }
 
+    /// Returns the traffic counters of this `Host`, accumulated since its creation or the last call to `Host::take_traffic_stats`.
+    ///
+    /// The counters are 32-bit and wrap around, so they should be reset regularly using `Host::take_traffic_stats`.
+    pub fn traffic_stats(&self) -> TrafficStats {
+        let host = unsafe { &*self.inner };
+
+        TrafficStats {
+            sent_data: host.totalSentData,
+            sent_packets: host.totalSentPackets,
+            received_data: host.totalReceivedData,
+            received_packets: host.totalReceivedPackets,
+        }
+    }
+
+    /// Returns the traffic counters of this `Host`, and resets them to 0.
+    ///
+    /// Calling this once per tick allows computing throughput without the counters overflowing.
+    pub fn take_traffic_stats(&mut self) -> TrafficStats {
+        let stats = self.traffic_stats();
+
+        unsafe {
+            (*self.inner).totalSentData = 0;
+            (*self.inner).totalSentPackets = 0;
+            (*self.inner).totalReceivedData = 0;
+            (*self.inner).totalReceivedPackets = 0;
+        }
+
+        stats
+    }
+
     /// Returns the number of peers allocated for this `Host`.
     pub fn peer_count(&self) -> usize {
         unsafe { (*self.inner).peerCount }
     }
 
+    /// Returns the peer identified by `peer_id`, or `None` if its connection has ended.
+    pub fn peer(&'_ self, peer_id: PeerId) -> Option<Peer<'_, T>> {
+        if peer_id.index() >= self.peer_count() {
+            return None;
+        }
+
+        let peer = self.get_peer(peer_id.index());
+
+        if peer.state() == PeerState::Disconnected || peer.id() != peer_id {
+            return None;
+        }
+
+        Some(peer)
+    }
+
     /// Returns an iterator over all peers connected to this `Host`.
     pub fn get_peer(&'_ self, index: usize) -> Peer<'_, T> {
         let raw_peers =
             unsafe { std::slice::from_raw_parts_mut((*self.inner).peers, (*self.inner).peerCount) };
 
-        Peer::new(&mut raw_peers[index])
+        Peer::new(&mut raw_peers[index], self.custom_checksum())
     }
 
     /// Returns an iterator over all peers connected to this `Host`.
@@ -152,7 +418,9 @@ impl<T> Host<T> {
         let raw_peers =
             unsafe { std::slice::from_raw_parts_mut((*self.inner).peers, (*self.inner).peerCount) };
 
-        raw_peers.into_iter().map(|rp| Peer::new(rp))
+        let checksum = self.custom_checksum();
+
+        raw_peers.into_iter().map(move |rp| Peer::new(rp, checksum))
     }
 
     /// Returns an iterator over all peers connected to this `Host`.
@@ -160,44 +428,135 @@ impl<T> Host<T> {
         let raw_peers =
             unsafe { std::slice::from_raw_parts_mut((*self.inner).peers, (*self.inner).peerCount) };
 
-        raw_peers.into_iter().map(|rp| Peer::new(rp))
+        let checksum = self.custom_checksum();
+
+        raw_peers.into_iter().map(move |rp| Peer::new(rp, checksum))
     }
 
     /// Maintains this host and delivers an event if available.
     ///
     /// This should be called regularly for ENet to work properly with good performance.
     pub fn service(&'_ mut self, timeout_ms: u32) -> Result<Option<Event<'_, T>>, Error> {
+        match self.service_sys(timeout_ms)? {
+            Some(sys_event) => Ok(self.process_sys_event(&sys_event)),
+            None => Ok(None),
+        }
+    }
+
+    /// Maintains this host and appends all available events to `events`, as `OwnedEvent`s.
+    ///
+    /// Waits up to `timeout_ms` for the first event, and then collects all events that are already queued,
+    /// without waiting any further. This fits a fixed-tick loop, which services the host once per tick.
+    /// Returns the number of events that were appended. If an error occurs, the events collected so far
+    /// are kept in `events`.
+    pub fn service_batch(
+        &mut self,
+        timeout_ms: u32,
+        events: &mut Vec<OwnedEvent>,
+    ) -> Result<usize, Error> {
+        let previous_len = events.len();
+
+        if let Some(sys_event) = self.service_sys(timeout_ms)? {
+            events.extend(self.process_sys_event(&sys_event).map(Event::into_owned));
+
+            while let Some(sys_event) = self.check_events_sys()? {
+                events.extend(self.process_sys_event(&sys_event).map(Event::into_owned));
+            }
+        }
+
+        Ok(events.len() - previous_len)
+    }
+
+    /// Like `Host::service`, but returns the raw `ENetEvent`, which has to be passed to `process_sys_event`.
+    pub(crate) fn service_sys(&mut self, timeout_ms: u32) -> Result<Option<ENetEvent>, Error> {
         // ENetEvent is Copy (aka has no Drop impl), so we don't have to make sure we `mem::forget` it later on
         let mut sys_event: ENetEvent = unsafe { std::mem::uninitialized() };
 
+        let timeout_ms = match self.simulator.as_mut() {
+            Some(simulator) => {
+                simulator.wake_up(self.inner);
+                simulator.limit_timeout(timeout_ms)
+            }
+            None => timeout_ms,
+        };
+
+        let _checksum = self.activate_checksum();
+        let _intercept = ActiveInterceptGuard::new(self.intercept.as_mut().map(|f| &mut **f));
+        let _simulator = ActiveSimulatorGuard::new(self.simulator.as_mut());
+        let _recorder = ActiveRecorderGuard::new(self.recorder.as_mut());
         let res =
             unsafe { enet_host_service(self.inner, &mut sys_event as *mut ENetEvent, timeout_ms) };
 
         match res {
-            r if r > 0 => Ok(Event::from_sys_event(self, &sys_event)),
+            r if r > 0 => Ok(Some(sys_event)),
             0 => Ok(None),
             r if r < 0 => Err(Error(r)),
             _ => panic!("unreachable"),
         }
-
-        // TODO: check `total*` fields on `inner`, these need to be reset from time to time.
     }
 
     /// Checks for any queued events on this `Host` and dispatches one if available
     pub fn check_events(&'_ mut self) -> Result<Option<Event<'_, T>>, Error> {
+        match self.check_events_sys()? {
+            Some(sys_event) => Ok(self.process_sys_event(&sys_event)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `Host::check_events`, but returns the raw `ENetEvent`, which has to be passed to `process_sys_event`.
+    pub(crate) fn check_events_sys(&mut self) -> Result<Option<ENetEvent>, Error> {
         // ENetEvent is Copy (aka has no Drop impl), so we don't have to make sure we `mem::forget` it later on
         let mut sys_event: ENetEvent = unsafe { std::mem::uninitialized() };
 
         let res = unsafe { enet_host_check_events(self.inner, &mut sys_event as *mut ENetEvent) };
 
         match res {
-            r if r > 0 => Ok(Event::from_sys_event(self, &sys_event)),
+            r if r > 0 => Ok(Some(sys_event)),
             0 => Ok(None),
             r if r < 0 => Err(Error(r)),
             _ => panic!("unreachable"),
         }
     }
 
+    /// Queues a packet to be sent to all connected peers of this `Host`.
+    ///
+    /// All peers share the same packet, so it is only allocated once.
+    /// Actual sending will happen during `Host::service`.
+    pub fn broadcast(&mut self, packet: Packet, channel_id: u8) {
+        unsafe {
+            enet_host_broadcast(self.inner, channel_id, packet.into_inner());
+        }
+    }
+
+    /// Queues a packet to be sent to all connected peers of this `Host` for which `filter` returns `true`.
+    ///
+    /// All selected peers share the same packet, so it is only allocated once.
+    /// Actual sending will happen during `Host::service`.
+    pub fn broadcast_filtered<F>(&mut self, packet: Packet, channel_id: u8, mut filter: F)
+    where
+        F: FnMut(&Peer<'_, T>) -> bool,
+    {
+        let raw_packet = packet.into_inner();
+
+        for peer in self.peers() {
+            if peer.state() != PeerState::Connected || !filter(&peer) {
+                continue;
+            }
+
+            // like `enet_host_broadcast`, ignore failures for individual peers
+            unsafe {
+                enet_peer_send(peer.as_raw(), channel_id, raw_packet);
+            }
+        }
+
+        // nobody took a reference to the packet, so it has to be freed here
+        unsafe {
+            if (*raw_packet).referenceCount == 0 {
+                enet_packet_destroy(raw_packet);
+            }
+        }
+    }
+
     /// Initiates a connection to a foreign host.
     ///
     /// The connection will not be done until a `Event::Connected` for this peer was received.
@@ -223,7 +582,53 @@ impl<T> Host<T> {
             return Err(Error(0));
         }
 
-        Ok(Peer::new(res))
+        let peer = Peer::new(res, self.custom_checksum());
+        let peer_id = peer.id();
+        self.connect_ids[peer_id.index()] = peer_id.connect_id();
+
+        Ok(peer)
+    }
+
+    /// Initiates a connection to a foreign host, allocating the channels declared in `layout`.
+    ///
+    /// See `Host::connect`.
+    pub fn connect_with_layout(
+        &mut self,
+        address: &Address,
+        layout: &ChannelLayout,
+        user_data: u32,
+    ) -> Result<Peer<'_, T>, Error> {
+        self.connect(address, layout.channel_count(), user_data)
+    }
+
+    /// Creates an `Event` from `sys_event`, keeping track of the connect ids of all connections.
+    pub(crate) fn process_sys_event(&mut self, sys_event: &ENetEvent) -> Option<Event<'_, T>> {
+        if sys_event.peer.is_null() {
+            return None;
+        }
+
+        let checksum = self.custom_checksum();
+        let peer = Peer::<T>::new(sys_event.peer, checksum);
+        let index = peer.index();
+
+        #[allow(non_upper_case_globals)]
+        let peer_id = match sys_event.type_ {
+            _ENetEventType_ENET_EVENT_TYPE_CONNECT => {
+                self.connect_ids[index] = peer.id().connect_id();
+                peer.id()
+            }
+            _ENetEventType_ENET_EVENT_TYPE_DISCONNECT => {
+                PeerId::new(index, std::mem::replace(&mut self.connect_ids[index], 0))
+            }
+            _ => peer.id(),
+        };
+
+        let event = Event::from_sys_event(sys_event, peer_id, checksum);
+        if let (Some(recorder), Some(event)) = (self.recorder.as_mut(), event.as_ref()) {
+            recorder.record_event(event);
+        }
+
+        event
     }
 }
 
@@ -0,0 +1,143 @@
+use std::cell::Cell;
+use std::os::raw::{c_int, c_void};
+
+use enet_sys::{
+    enet_socket_get_address, enet_socket_send, ENetAddress, ENetBuffer, ENetEvent, ENetHost,
+};
+
+use crate::record::record_datagram;
+use crate::simulator::simulate_datagram;
+use crate::{Address, Error};
+
+pub(crate) type InterceptFn = dyn FnMut(&Address, &[u8]) -> InterceptAction + Send;
+
+thread_local! {
+    /// The intercept hook of the `Host` that is currently being serviced on this thread, if any.
+    static ACTIVE_INTERCEPT: Cell<Option<*mut InterceptFn>> = Cell::new(None);
+    /// The host whose intercept hook is currently running on this thread, or null.
+    static INTERCEPTING_HOST: Cell<*mut ENetHost> = Cell::new(std::ptr::null_mut());
+}
+
+/// The action ENet should take for a datagram inspected by an intercept hook.
+///
+/// See `Host::set_intercept`.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum InterceptAction {
+    /// The datagram was handled by the hook, and will be ignored by ENet.
+    Consume,
+    /// The datagram is passed on to ENet, and processed as usual.
+    PassThrough,
+    /// Servicing the `Host` is aborted, and `Host::service` returns an error.
+    Error,
+}
+
+impl InterceptAction {
+    fn to_sys_result(self) -> c_int {
+        match self {
+            InterceptAction::Consume => 1,
+            InterceptAction::PassThrough => 0,
+            InterceptAction::Error => -1,
+        }
+    }
+}
+
+/// A handle to the socket of a `Host`, which can be used to send raw datagrams from inside its intercept hook.
+///
+/// Created through `Host::raw_socket`.
+#[derive(Debug, Copy, Clone)]
+pub struct RawSocket {
+    host: *mut ENetHost,
+}
+
+// `host` is only dereferenced while its intercept hook is running on the current thread.
+unsafe impl Send for RawSocket {}
+
+impl RawSocket {
+    pub(crate) fn new(host: *mut ENetHost) -> RawSocket {
+        RawSocket { host }
+    }
+
+    /// Sends `data` as a single raw datagram to `address`, bypassing the ENet protocol.
+    ///
+    /// This only works from inside the intercept hook of the `Host` this socket belongs to, and fails otherwise.
+    /// Returns the number of bytes sent, which is 0 if the datagram could not be sent right now.
+    pub fn send_to(&self, address: &Address, data: &[u8]) -> Result<usize, Error> {
+        if INTERCEPTING_HOST.with(Cell::get) != self.host {
+            return Err(Error(0));
+        }
+
+        let buffer = ENetBuffer {
+            data: data.as_ptr() as *mut c_void,
+            dataLength: data.len(),
+        };
+
+        let res = unsafe {
+            enet_socket_send(
+                (*self.host).socket,
+                &address.to_enet_address() as *const _,
+                &buffer as *const _,
+                1,
+            )
+        };
+
+        match res {
+            r if r >= 0 => Ok(r as usize),
+            r => Err(Error(r)),
+        }
+    }
+}
+
+/// Returns the address the socket of `host` is bound to, or `None` if it can not be determined.
+pub(crate) unsafe fn socket_address(host: *mut ENetHost) -> Option<Address> {
+    let mut address = ENetAddress { host: 0, port: 0 };
+
+    if enet_socket_get_address((*host).socket, &mut address as *mut _) < 0 {
+        return None;
+    }
+
+    Some(Address::from_enet_address(&address))
+}
+
+/// Makes an intercept hook available to ENet on this thread, until the returned guard is dropped.
+pub(crate) struct ActiveInterceptGuard {
+    previous: Option<*mut InterceptFn>,
+}
+
+impl ActiveInterceptGuard {
+    pub(crate) fn new(intercept: Option<&mut InterceptFn>) -> ActiveInterceptGuard {
+        let previous =
+            ACTIVE_INTERCEPT.with(|active| active.replace(intercept.map(|f| f as *mut _)));
+
+        ActiveInterceptGuard { previous }
+    }
+}
+
+impl Drop for ActiveInterceptGuard {
+    fn drop(&mut self) {
+        ACTIVE_INTERCEPT.with(|active| active.set(self.previous));
+    }
+}
+
+pub(crate) unsafe extern "C" fn intercept_trampoline(
+    host: *mut ENetHost,
+    _event: *mut ENetEvent,
+) -> c_int {
+    if simulate_datagram(host) == InterceptAction::Consume {
+        return InterceptAction::Consume.to_sys_result();
+    }
+    record_datagram(host);
+
+    let intercept = match ACTIVE_INTERCEPT.with(Cell::get) {
+        Some(f) => &mut *f,
+        None => return 0,
+    };
+
+    let address = Address::from_enet_address(&(*host).receivedAddress);
+    let data = std::slice::from_raw_parts((*host).receivedData, (*host).receivedDataLength);
+
+    let previous_host = INTERCEPTING_HOST.with(|h| h.replace(host));
+    let action = intercept(&address, data);
+    INTERCEPTING_HOST.with(|h| h.set(previous_host));
+
+    action.to_sys_result()
+}
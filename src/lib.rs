@@ -27,6 +27,7 @@
 //! ENet claims to be "mostly" thread-safe as long as access to individual `Host`-instances is handled in a synchronized manner.
 //! This is kind of an unclear statement, but this API tries to follow that as good as possible.
 //! So if the rust compilers allows you to send/sync an object between threads, it should be safe to do so.
+//! To use a `Host` from multiple threads, it can be moved to its own service thread using `Host::spawn_thread`.
 //!
 //! If you used no unsafe code and the library blows up in your face, that is considered a bug. Please report any bug you encounter via [github](https://github.com/futile/enet-rs).
 
@@ -50,16 +51,43 @@ use std::{
 use enet_sys::{enet_deinitialize, enet_host_create, enet_initialize, enet_linked_version};
 
 mod address;
+mod channel;
+mod checksum;
+mod compressor;
 mod event;
+mod handle;
 mod host;
+mod intercept;
+pub mod loopback;
+#[cfg(feature = "serde")]
+mod message;
 mod packet;
 mod peer;
+pub mod protocol;
+pub mod record;
+pub mod rpc;
+mod simulator;
+pub mod time;
+#[cfg(all(feature = "tokio", unix))]
+pub mod tokio;
 
-pub use crate::address::Address;
-pub use crate::event::Event;
-pub use crate::host::{BandwidthLimit, ChannelLimit, Host};
-pub use crate::packet::{Packet, PacketMode};
-pub use crate::peer::{Peer, PeerPacket, PeerState};
+pub use crate::address::{Address, AddressError};
+pub use crate::channel::{Channel, ChannelError, ChannelLayout};
+pub use crate::checksum::ChecksumKind;
+pub use crate::compressor::{Compressor, RangeCoder};
+pub use crate::event::{Event, OwnedEvent};
+pub use crate::handle::{HandleError, HostHandle};
+pub use crate::host::{BandwidthLimit, ChannelLimit, Host, TrafficStats};
+pub use crate::intercept::{InterceptAction, RawSocket};
+#[cfg(feature = "bincode")]
+pub use crate::message::BincodeCodec;
+#[cfg(feature = "postcard")]
+pub use crate::message::PostcardCodec;
+#[cfg(feature = "serde")]
+pub use crate::message::{Codec, DecodeError, JsonCodec, SendMessageError};
+pub use crate::packet::{Packet, PacketFlags, PacketMode};
+pub use crate::peer::{Peer, PeerId, PeerPacket, PeerState, PeerStats};
+pub use crate::simulator::{NetworkConditions, NetworkSimulator, SimulatorStats};
 
 pub use enet_sys::ENetVersion as EnetVersion;
 
@@ -163,6 +191,24 @@ impl Enet {
 
         Ok(Host::new(self.keep_alive.clone(), inner))
     }
+
+    /// Creates a `Host` that allows as many channels as declared in `layout`, see `Enet::create_host`.
+    pub fn create_host_with_layout<T>(
+        &self,
+        address: Option<&Address>,
+        max_peer_count: usize,
+        layout: &ChannelLayout,
+        incoming_bandwidth: BandwidthLimit,
+        outgoing_bandwidth: BandwidthLimit,
+    ) -> Result<Host<T>, Error> {
+        self.create_host(
+            address,
+            max_peer_count,
+            layout.channel_limit(),
+            incoming_bandwidth,
+            outgoing_bandwidth,
+        )
+    }
 }
 
 /// Returns the version of the linked ENet library.
@@ -188,12 +234,128 @@ impl Drop for EnetKeepAlive {
 
 #[cfg(test)]
 mod tests {
-    use super::{BandwidthLimit, ChannelLimit, Enet};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::{
+        Address, BandwidthLimit, ChannelLimit, ChecksumKind, Compressor, Enet, Event, HandleError,
+        Host, InterceptAction, OwnedEvent, TrafficStats,
+    };
+    use super::{Packet, PacketMode};
 
     lazy_static! {
         static ref ENET: Enet = Enet::new().unwrap();
     }
 
+    /// Creates a host, which listens on a free port of localhost if `listen` is true.
+    fn create_host(listen: bool) -> Host<()> {
+        let address = if listen {
+            Some(Address::new(Ipv4Addr::LOCALHOST, 0))
+        } else {
+            None
+        };
+
+        ENET.create_host::<()>(
+            address.as_ref(),
+            4,
+            ChannelLimit::Maximum,
+            BandwidthLimit::Unlimited,
+            BandwidthLimit::Unlimited,
+        )
+        .unwrap()
+    }
+
+    /// Services both hosts until `client` is connected to `server`.
+    ///
+    /// Returns whether the connection could be established.
+    fn try_connect_pair(server: &mut Host<()>, client: &mut Host<()>) -> bool {
+        client.connect(&server.address(), 2, 0).unwrap();
+
+        let (mut server_connected, mut client_connected) = (false, false);
+
+        for _ in 0..100 {
+            if let Some(Event::Connect(..)) = server.service(10).unwrap() {
+                server_connected = true;
+            }
+            if let Some(Event::Connect(..)) = client.service(10).unwrap() {
+                client_connected = true;
+            }
+            if server_connected && client_connected {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn connect_pair(server: &mut Host<()>, client: &mut Host<()>) {
+        assert!(
+            try_connect_pair(server, client),
+            "could not connect client to server on {}",
+            server.address()
+        );
+    }
+
+    /// Services both hosts until `server` receives a packet, and returns its contents.
+    fn receive_packet(server: &mut Host<()>, client: &mut Host<()>) -> Option<Vec<u8>> {
+        for _ in 0..100 {
+            client.service(10).unwrap();
+
+            if let Some(Event::Receive { ref packet, .. }) = server.service(10).unwrap() {
+                return Some(packet.data().to_vec());
+            }
+        }
+
+        None
+    }
+
+    /// A simple run-length encoding, which counts how often it decompressed a datagram.
+    struct RunLengthCompressor {
+        decompressed: Arc<AtomicUsize>,
+    }
+
+    impl Compressor for RunLengthCompressor {
+        fn compress(&mut self, in_buffers: &[&[u8]], _: usize, out: &mut [u8]) -> Option<usize> {
+            let mut bytes = in_buffers.iter().flat_map(|b| b.iter()).cloned().peekable();
+            let mut len = 0;
+
+            while let Some(byte) = bytes.next() {
+                let mut run = 1u8;
+                while run < u8::max_value() && bytes.peek() == Some(&byte) {
+                    bytes.next();
+                    run += 1;
+                }
+
+                if len + 2 > out.len() {
+                    return None;
+                }
+                out[len] = run;
+                out[len + 1] = byte;
+                len += 2;
+            }
+
+            Some(len)
+        }
+
+        fn decompress(&mut self, input: &[u8], out: &mut [u8]) -> Option<usize> {
+            let mut len = 0;
+
+            for pair in input.chunks(2) {
+                if pair.len() != 2 || len + pair[0] as usize > out.len() {
+                    return None;
+                }
+                for b in &mut out[len..len + pair[0] as usize] {
+                    *b = pair[1];
+                }
+                len += pair[0] as usize;
+            }
+
+            self.decompressed.fetch_add(1, Ordering::SeqCst);
+            Some(len)
+        }
+    }
+
     #[test]
     fn test_enet_new() {
         let _ = *ENET; // make sure the lazy_static is initialized
@@ -202,17 +364,1356 @@ mod tests {
 
     #[test]
     fn test_host_create_localhost() {
-        use crate::Address;
-        use std::net::Ipv4Addr;
-
         let enet = &ENET;
-        enet.create_host::<()>(
-            Some(&Address::new(Ipv4Addr::LOCALHOST, 12345)),
-            1,
-            ChannelLimit::Maximum,
-            BandwidthLimit::Unlimited,
-            BandwidthLimit::Unlimited,
+        let host = enet
+            .create_host::<()>(
+                Some(&Address::new(Ipv4Addr::LOCALHOST, 0)),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+
+        // the port chosen by the OS is reported
+        assert_eq!(host.address().ip(), &Ipv4Addr::LOCALHOST);
+        assert_ne!(host.address().port(), 0);
+    }
+
+    #[test]
+    fn test_range_coder_compression() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.enable_range_coder_compression().unwrap();
+        client.enable_range_coder_compression().unwrap();
+        connect_pair(&mut server, &mut client);
+
+        let payload = vec![7u8; 512];
+        let mut peer = client.peers().next().unwrap();
+        peer.send_packet(Packet::new(&payload, PacketMode::ReliableSequenced).unwrap(), 0)
+            .unwrap();
+
+        assert_eq!(receive_packet(&mut server, &mut client), Some(payload));
+    }
+
+    #[test]
+    fn test_custom_compressor() {
+        let decompressed = Arc::new(AtomicUsize::new(0));
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_compressor(RunLengthCompressor {
+            decompressed: decompressed.clone(),
+        });
+        client.set_compressor(RunLengthCompressor {
+            decompressed: Arc::new(AtomicUsize::new(0)),
+        });
+        connect_pair(&mut server, &mut client);
+
+        let payload = vec![0u8; 1000];
+        let mut peer = client.peers().next().unwrap();
+        peer.send_packet(Packet::new(&payload, PacketMode::ReliableSequenced).unwrap(), 0)
+            .unwrap();
+
+        assert_eq!(receive_packet(&mut server, &mut client), Some(payload));
+        assert!(decompressed.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn test_crc32_checksum() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_checksum(ChecksumKind::Crc32);
+        client.set_checksum(ChecksumKind::Crc32);
+
+        assert!(try_connect_pair(&mut server, &mut client));
+    }
+
+    #[test]
+    fn test_custom_checksum() {
+        let sum = |buffers: &[&[u8]]| {
+            buffers
+                .iter()
+                .flat_map(|b| b.iter())
+                .fold(0u32, |acc, &b| acc.rotate_left(5) ^ u32::from(b))
+        };
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_checksum(ChecksumKind::Custom(Box::new(sum)));
+        client.set_checksum(ChecksumKind::Custom(Box::new(sum)));
+
+        assert!(try_connect_pair(&mut server, &mut client));
+    }
+
+    #[test]
+    fn test_mismatched_checksums() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_checksum(ChecksumKind::Crc32);
+        client.set_checksum(ChecksumKind::None);
+
+        assert!(!try_connect_pair(&mut server, &mut client));
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_checksum(ChecksumKind::Crc32);
+        client.set_checksum(ChecksumKind::Custom(Box::new(|_: &[&[u8]]| 42)));
+
+        assert!(!try_connect_pair(&mut server, &mut client));
+    }
+
+    #[test]
+    fn test_custom_checksum_disconnect_now() {
+        let sum = |buffers: &[&[u8]]| buffers.iter().map(|b| b.len() as u32).sum::<u32>() ^ 0x5a5a;
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_checksum(ChecksumKind::Custom(Box::new(sum)));
+        client.set_checksum(ChecksumKind::Custom(Box::new(sum)));
+        connect_pair(&mut server, &mut client);
+
+        // the notification is sent outside of `Host::service`, and must still carry a valid checksum
+        client.peers().next().unwrap().disconnect_now(5);
+
+        let mut disconnected = false;
+        for _ in 0..100 {
+            if let Some(Event::Disconnect(_, _, 5)) = server.service(10).unwrap() {
+                disconnected = true;
+                break;
+            }
+        }
+        assert!(disconnected);
+    }
+
+    #[test]
+    fn test_intercept_raw_datagrams() {
+        use std::net::{SocketAddr, UdpSocket};
+        use std::time::Duration;
+
+        let mut server = create_host(true);
+        let socket = server.raw_socket();
+        server.set_intercept(move |address, data| {
+            if data == b"ping" {
+                socket.send_to(address, b"pong").unwrap();
+                InterceptAction::Consume
+            } else {
+                InterceptAction::PassThrough
+            }
+        });
+
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        udp.set_nonblocking(true).unwrap();
+        udp.send_to(b"ping", SocketAddr::from(server.address()))
+            .unwrap();
+
+        let mut reply = [0u8; 16];
+        for _ in 0..100 {
+            server.service(10).unwrap();
+
+            if let Ok((len, _)) = udp.recv_from(&mut reply) {
+                assert_eq!(&reply[..len], b"pong");
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        panic!("no reply from intercept hook");
+    }
+
+    #[test]
+    fn test_intercept_pass_through() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_intercept(|_, _| InterceptAction::PassThrough);
+
+        assert!(try_connect_pair(&mut server, &mut client));
+        assert!(server
+            .raw_socket()
+            .send_to(&client.address(), b"x")
+            .is_err());
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let mut server = create_host(true);
+        let mut first = create_host(false);
+        let mut second = create_host(false);
+        connect_pair(&mut server, &mut first);
+        connect_pair(&mut server, &mut second);
+
+        server.broadcast(
+            Packet::new(b"all", PacketMode::ReliableSequenced).unwrap(),
+            0,
+        );
+
+        assert_eq!(
+            receive_packet(&mut first, &mut server),
+            Some(b"all".to_vec())
+        );
+        assert_eq!(
+            receive_packet(&mut second, &mut server),
+            Some(b"all".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_broadcast_filtered() {
+        let mut server = create_host(true);
+        let mut first = create_host(false);
+        let mut second = create_host(false);
+        connect_pair(&mut server, &mut first);
+        connect_pair(&mut server, &mut second);
+
+        let first_address = server.peers().next().unwrap().address();
+        server.broadcast_filtered(
+            Packet::new(b"first", PacketMode::ReliableSequenced).unwrap(),
+            0,
+            |peer| peer.address() == first_address,
+        );
+
+        assert_eq!(
+            receive_packet(&mut first, &mut server),
+            Some(b"first".to_vec())
+        );
+        assert_eq!(receive_packet(&mut second, &mut server), None);
+
+        // a packet that is not sent to anyone is freed right away
+        server.broadcast_filtered(
+            Packet::new(b"none", PacketMode::ReliableSequenced).unwrap(),
+            0,
+            |_| false,
+        );
+    }
+
+    /// A buffer that counts how often it was dropped.
+    struct DropCounter {
+        data: Vec<u8>,
+        drops: Arc<AtomicUsize>,
+    }
+
+    impl AsRef<[u8]> for DropCounter {
+        fn as_ref(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_packet_from_buffer_destroy() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let buffer = DropCounter {
+            data: vec![1, 2, 3],
+            drops: drops.clone(),
+        };
+
+        let packet = Packet::from_buffer(buffer, PacketMode::ReliableSequenced).unwrap();
+        assert_eq!(packet.data(), &[1, 2, 3]);
+        assert_eq!(packet.packet_mode(), PacketMode::ReliableSequenced);
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+
+        drop(packet);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_packet_from_buffer_send() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let buffer = DropCounter {
+            data: vec![42; 4096],
+            drops: drops.clone(),
+        };
+
+        let mut peer = client.peers().next().unwrap();
+        peer.send_packet(
+            Packet::from_buffer(buffer, PacketMode::ReliableSequenced).unwrap(),
+            0,
         )
         .unwrap();
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+
+        assert_eq!(
+            receive_packet(&mut server, &mut client),
+            Some(vec![42; 4096])
+        );
+
+        // the buffer is released once the packet has been acknowledged
+        for _ in 0..100 {
+            if drops.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            server.service(10).unwrap();
+            client.service(10).unwrap();
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_packet_from_vec() {
+        let packet = Packet::from_vec(vec![1, 2, 3], PacketMode::UnreliableSequenced).unwrap();
+        assert_eq!(packet.data(), &[1, 2, 3]);
+        assert_eq!(packet.packet_mode(), PacketMode::UnreliableSequenced);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_packet_from_bytes() {
+        let packet = Packet::from_bytes(
+            bytes::Bytes::from_static(b"static"),
+            PacketMode::UnreliableUnsequenced,
+        )
+        .unwrap();
+        assert_eq!(packet.data(), b"static");
+    }
+
+    #[test]
+    fn test_unreliable_fragmented_packet() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let payload = vec![3u8; 8000];
+        let mut peer = client.peers().next().unwrap();
+        peer.send_packet(
+            Packet::new(&payload, PacketMode::UnreliableFragmented).unwrap(),
+            0,
+        )
+        .unwrap();
+
+        for _ in 0..100 {
+            client.service(10).unwrap();
+
+            if let Some(Event::Receive { ref packet, .. }) = server.service(10).unwrap() {
+                assert_eq!(packet.data(), &payload[..]);
+                assert_eq!(packet.packet_mode(), PacketMode::UnreliableFragmented);
+                return;
+            }
+        }
+
+        panic!("fragmented packet was not received");
+    }
+
+    #[test]
+    fn test_peer_stats() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let stats = client.peers().next().unwrap().stats();
+        assert!(stats.mtu > 0);
+        assert!(stats.packets_sent > 0);
+        assert!(stats.packet_loss >= 0.0 && stats.packet_loss <= 1.0);
+        assert!(stats.packet_throttle <= stats.packet_throttle_limit);
+    }
+
+    #[test]
+    fn test_traffic_stats() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let stats = client.take_traffic_stats();
+        assert!(stats.sent_packets > 0 && stats.sent_data > 0);
+        assert!(stats.received_packets > 0 && stats.received_data > 0);
+        assert_eq!(client.traffic_stats(), TrafficStats::default());
+
+        let stats = server.traffic_stats();
+        assert!(stats.received_packets > 0);
+        assert_eq!(server.traffic_stats(), stats);
+    }
+
+    #[test]
+    fn test_connect_data() {
+        const PROTOCOL_VERSION: u32 = 7;
+
+        let mut server = create_host(true);
+        let mut accepted = create_host(false);
+        let mut rejected = create_host(false);
+        accepted
+            .connect(&server.address(), 1, PROTOCOL_VERSION)
+            .unwrap();
+        rejected
+            .connect(&server.address(), 1, PROTOCOL_VERSION + 1)
+            .unwrap();
+
+        let mut versions = Vec::new();
+        let mut rejected_with = None;
+
+        for _ in 0..100 {
+            accepted.service(10).unwrap();
+
+            if let Some(Event::Disconnect(_, _, reason)) = rejected.service(10).unwrap() {
+                rejected_with = Some(reason);
+            }
+
+            if let Some(Event::Connect(ref mut peer, _, version)) = server.service(10).unwrap() {
+                versions.push(version);
+                if version != PROTOCOL_VERSION {
+                    peer.disconnect(PROTOCOL_VERSION);
+                }
+            }
+
+            if versions.len() == 2 && rejected_with.is_some() {
+                break;
+            }
+        }
+
+        versions.sort();
+        assert_eq!(versions, vec![PROTOCOL_VERSION, PROTOCOL_VERSION + 1]);
+        assert_eq!(rejected_with, Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn test_peer_id() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        client.connect(&server.address(), 1, 0).unwrap();
+
+        let mut server_id = None;
+        for _ in 0..100 {
+            client.service(10).unwrap();
+
+            if let Some(Event::Connect(_, id, _)) = server.service(10).unwrap() {
+                server_id = Some(id);
+                break;
+            }
+        }
+
+        let server_id = server_id.expect("no connection");
+        assert_eq!(server.peer(server_id).unwrap().id(), server_id);
+
+        let client_id = client.peers().next().unwrap().id();
+        client.peer(client_id).unwrap().disconnect(0);
+
+        let mut disconnected_id = None;
+        for _ in 0..100 {
+            client.service(10).unwrap();
+
+            if let Some(Event::Disconnect(_, id, _)) = server.service(10).unwrap() {
+                disconnected_id = Some(id);
+                break;
+            }
+        }
+
+        assert_eq!(disconnected_id, Some(server_id));
+        assert!(server.peer(server_id).is_none());
+
+        // the peer's slot is reused for a new connection, which gets a different id
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+        let new_id = server.peers().next().unwrap().id();
+        assert_eq!(new_id.index(), server_id.index());
+        assert_ne!(new_id, server_id);
+        assert!(server.peer(server_id).is_none());
+    }
+
+    #[test]
+    fn test_into_owned() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let mut peer = client.peers().next().unwrap();
+        let packet = Packet::new(b"owned", PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet, 1).unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..100 {
+            client.service(10).unwrap();
+
+            if let Some(event) = server.service(10).unwrap() {
+                events.push(event.into_owned());
+
+                // ENet discards packets that arrive together with a disconnect, so only disconnect afterwards
+                if events.len() == 1 {
+                    client.peers().next().unwrap().disconnect(42);
+                }
+            }
+            if events.len() == 2 {
+                break;
+            }
+        }
+
+        // the server can be used again while the events are kept around
+        server.flush();
+
+        match &events[..] {
+            [OwnedEvent::Receive {
+                channel_id,
+                packet,
+                peer_id,
+                ..
+            }, OwnedEvent::Disconnect { data, .. }] => {
+                assert_eq!(*channel_id, 1);
+                assert_eq!(packet.data(), b"owned");
+                assert_eq!(events[1].peer_id(), *peer_id);
+                assert_eq!(*data, 42);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+        assert_eq!(events[0].address().ip(), &Ipv4Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn test_service_batch() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let mut peer = client.peers().next().unwrap();
+        for i in 0..3u8 {
+            let packet = Packet::new(&[i], PacketMode::ReliableSequenced).unwrap();
+            peer.send_packet(packet, 0).unwrap();
+        }
+        client.flush();
+
+        let mut events = Vec::new();
+        for _ in 0..100 {
+            client.service(0).unwrap();
+            server.service_batch(10, &mut events).unwrap();
+            if events.len() >= 3 {
+                break;
+            }
+        }
+
+        let data: Vec<u8> = events
+            .iter()
+            .map(|event| match event {
+                OwnedEvent::Receive { packet, .. } => packet.data()[0],
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(data, vec![0, 1, 2]);
+        assert_eq!(server.service_batch(0, &mut events).unwrap(), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_messages() {
+        use crate::Codec;
+        use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Move {
+            x: i32,
+            y: i32,
+        }
+
+        /// Encodes messages as JSON, prefixed with a version byte.
+        struct VersionedCodec;
+
+        impl Codec for VersionedCodec {
+            fn encode<M: Serialize + ?Sized>(
+                &self,
+                message: &M,
+            ) -> Result<Vec<u8>, failure::Error> {
+                let mut data = vec![1];
+                data.extend(crate::JsonCodec.encode(message)?);
+                Ok(data)
+            }
+
+            fn decode<M: DeserializeOwned>(&self, data: &[u8]) -> Result<M, failure::Error> {
+                match data.split_first() {
+                    Some((1, message)) => crate::JsonCodec.decode(message),
+                    _ => Err(failure::err_msg("unknown version")),
+                }
+            }
+        }
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let mut peer = client.peers().next().unwrap();
+        peer.send_message(&Move { x: 1, y: -2 }, 0, PacketMode::ReliableSequenced)
+            .unwrap();
+        let data = receive_packet(&mut server, &mut client).unwrap();
+        let packet = Packet::new(&data, PacketMode::ReliableSequenced).unwrap();
+        assert_eq!(packet.decode::<Move>().unwrap(), Move { x: 1, y: -2 });
+        assert!(packet.decode::<String>().is_err());
+
+        let mut peer = client.peers().next().unwrap();
+        peer.send_message_with(&VersionedCodec, "hello", 0, PacketMode::ReliableSequenced)
+            .unwrap();
+        let data = receive_packet(&mut server, &mut client).unwrap();
+        let packet = Packet::new(&data, PacketMode::ReliableSequenced).unwrap();
+        assert_eq!(
+            packet.decode_with::<_, String>(&VersionedCodec).unwrap(),
+            "hello"
+        );
+        assert!(packet.decode::<String>().is_err());
+    }
+
+    /// Sends a message from `client` to `server` using `codec`, and decodes it again.
+    #[cfg(any(feature = "bincode", feature = "postcard"))]
+    fn codec_round_trip<C: crate::Codec>(codec: &C) {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let message = (1u8, -2i32, "three".to_string(), vec![4.0f32]);
+        let mut peer = client.peers().next().unwrap();
+        peer.send_message_with(codec, &message, 0, PacketMode::ReliableSequenced)
+            .unwrap();
+        let data = receive_packet(&mut server, &mut client).unwrap();
+        let packet = Packet::new(&data, PacketMode::ReliableSequenced).unwrap();
+        assert_eq!(
+            packet
+                .decode_with::<_, (u8, i32, String, Vec<f32>)>(codec)
+                .unwrap(),
+            message
+        );
+        // the message is too short
+        assert!(packet
+            .decode_with::<_, (u8, i32, String, Vec<f32>, u64)>(codec)
+            .is_err());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_messages() {
+        codec_round_trip(&crate::BincodeCodec);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_postcard_messages() {
+        codec_round_trip(&crate::PostcardCodec);
+    }
+
+    #[test]
+    fn test_channel_layout() {
+        use crate::ChannelLayout;
+
+        let layout = ChannelLayout::new()
+            .channel("input", PacketMode::UnreliableUnsequenced)
+            .channel("chat", PacketMode::ReliableSequenced);
+
+        let mut server = ENET
+            .create_host_with_layout::<()>(
+                Some(&Address::new(Ipv4Addr::LOCALHOST, 0)),
+                4,
+                &layout,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let mut client = create_host(false);
+        client
+            .connect_with_layout(&server.address(), &layout, 0)
+            .unwrap();
+
+        let mut packets = Vec::new();
+        for _ in 0..100 {
+            if let Some(Event::Connect(ref mut peer, ..)) = client.service(10).unwrap() {
+                assert_eq!(peer.channel_count(), layout.channel_count());
+
+                let chat = layout.by_name("chat").unwrap();
+                peer.send_on(chat, b"hi").unwrap();
+                // sent on the wrong channel for its mode
+                peer.send_packet(Packet::new(b"bad", chat.mode()).unwrap(), 0)
+                    .unwrap();
+            }
+
+            if let Some(Event::Receive {
+                channel_id,
+                ref packet,
+                ..
+            }) = server.service(10).unwrap()
+            {
+                packets.push(
+                    layout
+                        .validate(channel_id, packet)
+                        .map(|channel| (channel.name().to_owned(), packet.data().to_vec())),
+                );
+            }
+            if packets.len() == 2 {
+                break;
+            }
+        }
+
+        assert_eq!(packets.len(), 2);
+        assert!(packets
+            .iter()
+            .any(|p| p.as_ref().ok() == Some(&("chat".to_owned(), b"hi".to_vec()))));
+        assert!(packets.iter().any(|p| p.is_err()));
+    }
+
+    #[test]
+    fn test_empty_channel_layout() {
+        use crate::ChannelLayout;
+
+        let mut server = ENET
+            .create_host_with_layout::<()>(
+                Some(&Address::new(Ipv4Addr::LOCALHOST, 0)),
+                1,
+                &ChannelLayout::new(),
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let mut client = create_host(false);
+        client.connect(&server.address(), 4, 0).unwrap();
+
+        let mut channel_count = None;
+        for _ in 0..100 {
+            server.service(10).unwrap();
+            if let Some(Event::Connect(ref peer, ..)) = client.service(10).unwrap() {
+                channel_count = Some(peer.channel_count());
+                break;
+            }
+        }
+
+        // a limit of zero would allow the maximum number of channels
+        assert_eq!(channel_count, Some(1));
+    }
+
+    #[test]
+    fn test_loopback_pair() {
+        use crate::loopback::LoopbackPair;
+
+        let mut pair = LoopbackPair::<()>::new(&ENET, 1, ChannelLimit::Maximum).unwrap();
+        let server_id = pair.connect(2, 0).unwrap();
+
+        // every step exchanges all datagrams, so the handshake completes in a single step
+        let events = pair.step().unwrap();
+        assert!(match (&events.server[..], &events.client[..]) {
+            ([OwnedEvent::Connect { .. }], [OwnedEvent::Connect { peer_id, .. }]) => {
+                *peer_id == server_id
+            }
+            _ => false,
+        });
+        let events = pair.step().unwrap();
+        assert!(events.server.is_empty() && events.client.is_empty());
+
+        let mut peer = pair.client().peer(server_id).unwrap();
+        for i in 0..3u8 {
+            let packet = Packet::new(&[i], PacketMode::ReliableSequenced).unwrap();
+            peer.send_packet(packet, 0).unwrap();
+        }
+        let packet = Packet::new(b"unreliable", PacketMode::UnreliableSequenced).unwrap();
+        peer.send_packet(packet, 1).unwrap();
+
+        let events = pair.step().unwrap();
+        let received: Vec<_> = events
+            .server
+            .iter()
+            .map(|event| match event {
+                OwnedEvent::Receive {
+                    channel_id, packet, ..
+                } => (*channel_id, packet.data().to_vec()),
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            received,
+            vec![
+                (0, vec![0]),
+                (0, vec![1]),
+                (0, vec![2]),
+                (1, b"unreliable".to_vec())
+            ]
+        );
+        assert!(events.client.is_empty());
+
+        pair.client().peer(server_id).unwrap().disconnect(0);
+        let events = pair.step().unwrap();
+        assert!(match (&events.server[..], &events.client[..]) {
+            ([OwnedEvent::Disconnect { .. }], [OwnedEvent::Disconnect { .. }]) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_rpc() {
+        use crate::rpc::{Rpc, RpcError};
+        use crate::time::ManualClock;
+        use std::time::Duration;
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let mut server_rpc = Rpc::new(1);
+        server_rpc.register("double", |_, payload| {
+            Ok(payload.iter().map(|b| b * 2).collect())
+        });
+        server_rpc.register("fail", |_, _| Err("no items".to_owned()));
+
+        let mut client_rpc = Rpc::new(1);
+        let mut peer = client.peers().next().unwrap();
+        let double = client_rpc.request(&mut peer, "double", &[1, 2]).unwrap();
+        let fail = client_rpc.request(&mut peer, "fail", &[]).unwrap();
+        let unknown = client_rpc.request(&mut peer, "unknown", &[]).unwrap();
+
+        let mut responses = Vec::new();
+        for _ in 0..100 {
+            if let Some(mut event) = server.service(10).unwrap() {
+                assert!(server_rpc.handle_event(&mut event));
+            }
+            if let Some(mut event) = client.service(10).unwrap() {
+                assert!(client_rpc.handle_event(&mut event));
+            }
+            while let Some(response) = client_rpc.next_response() {
+                responses.push((response.id, response.result));
+            }
+            if responses.len() == 3 {
+                break;
+            }
+        }
+
+        assert_eq!(
+            responses,
+            vec![
+                (double, Ok(vec![2, 4])),
+                (fail, Err(RpcError::Failed("no items".to_owned()))),
+                (unknown, Err(RpcError::UnknownMethod("unknown".to_owned()))),
+            ]
+        );
+
+        // requests time out if they are not answered
+        let clock = ManualClock::new();
+        client_rpc.set_clock(clock.clone());
+        let mut peer = client.peers().next().unwrap();
+        let timed_out = client_rpc.request(&mut peer, "double", &[]).unwrap();
+        assert!(client_rpc.next_response().is_none());
+        clock.advance(Duration::from_secs(5));
+        let response = client_rpc.next_response().unwrap();
+        assert_eq!(response.id, timed_out);
+        assert_eq!(response.result, Err(RpcError::TimedOut));
+
+        // and fail once the peer disconnects
+        let mut peer = client.peers().next().unwrap();
+        let cancelled = client_rpc.request(&mut peer, "double", &[]).unwrap();
+        peer.disconnect(0);
+
+        let mut response = None;
+        for _ in 0..100 {
+            server.service(10).unwrap();
+            if let Some(mut event) = client.service(10).unwrap() {
+                client_rpc.handle_event(&mut event);
+            }
+            response = client_rpc.next_response();
+            if response.is_some() {
+                break;
+            }
+        }
+
+        let response = response.expect("request was not cancelled");
+        assert_eq!(response.id, cancelled);
+        assert_eq!(response.result, Err(RpcError::Disconnected));
+        assert_eq!(client_rpc.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_simulated_loss() {
+        use crate::loopback::LoopbackPair;
+        use crate::{NetworkConditions, NetworkSimulator};
+        use std::thread;
+        use std::time::Duration;
+
+        const COUNT: u8 = 50;
+
+        fn received(events: &[OwnedEvent], channel: u8) -> Vec<u8> {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    OwnedEvent::Receive {
+                        channel_id, packet, ..
+                    } if *channel_id == channel => Some(packet.data()[0]),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let run = |seed| {
+            let mut pair = LoopbackPair::<()>::new(&ENET, 1, ChannelLimit::Maximum).unwrap();
+            let server_id = pair.connect(2, 0).unwrap();
+            pair.step().unwrap();
+
+            let conditions = NetworkConditions {
+                loss: 0.2,
+                ..NetworkConditions::default()
+            };
+            pair.server()
+                .set_network_simulator(NetworkSimulator::new(seed, conditions));
+
+            // every packet is sent in its own datagram, and the server sends nothing back, so the simulator
+            // sees the same datagrams in every run
+            let mut unreliable = Vec::new();
+            for i in 0..COUNT {
+                let packet = Packet::new(&[i], PacketMode::UnreliableSequenced).unwrap();
+                pair.client()
+                    .peer(server_id)
+                    .unwrap()
+                    .send_packet(packet, 1)
+                    .unwrap();
+                unreliable.extend(received(&pair.step().unwrap().server, 1));
+            }
+
+            // lost reliable packets are resent once ENet's resend timers expire
+            let mut reliable = Vec::new();
+            for i in 0..COUNT {
+                let packet = Packet::new(&[i], PacketMode::ReliableSequenced).unwrap();
+                pair.client()
+                    .peer(server_id)
+                    .unwrap()
+                    .send_packet(packet, 0)
+                    .unwrap();
+                reliable.extend(received(&pair.step().unwrap().server, 0));
+            }
+            for _ in 0..1000 {
+                if reliable.len() == usize::from(COUNT) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(5));
+                reliable.extend(received(&pair.step().unwrap().server, 0));
+            }
+
+            (unreliable, reliable)
+        };
+
+        let (unreliable, reliable) = run(7);
+        assert_eq!(reliable, (0..COUNT).collect::<Vec<_>>());
+        assert!(!unreliable.is_empty() && unreliable.len() < usize::from(COUNT));
+        assert!(unreliable.windows(2).all(|w| w[0] < w[1]));
+
+        assert_eq!(run(7).0, unreliable);
+    }
+
+    #[test]
+    fn test_simulator_wake_ups() {
+        use crate::simulator::WAKE_UP;
+        use crate::{NetworkConditions, NetworkSimulator};
+        use std::net::UdpSocket;
+        use std::time::Duration;
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(50),
+            ..NetworkConditions::default()
+        };
+        server.set_network_simulator(NetworkSimulator::new(0, conditions));
+        server.take_traffic_stats();
+
+        let mut peer = client.peers().next().unwrap();
+        let packet = Packet::new(b"delayed", PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet, 0).unwrap();
+        assert_eq!(
+            receive_packet(&mut server, &mut client),
+            Some(b"delayed".to_vec())
+        );
+
+        // wake-ups are not counted as received traffic
+        let received = server.network_simulator_mut().unwrap().stats().received;
+        assert_eq!(u64::from(server.traffic_stats().received_packets), received);
+
+        // wake-ups from other senders are delayed like any other datagram
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .send_to(WAKE_UP, (Ipv4Addr::LOCALHOST, server.address().port()))
+            .unwrap();
+        for _ in 0..100 {
+            server.service(1).unwrap();
+            if server.network_simulator_mut().unwrap().stats().received > received {
+                break;
+            }
+        }
+        let simulator = server.network_simulator_mut().unwrap();
+        assert_eq!(simulator.stats().received, received + 1);
+        assert_eq!(simulator.queued_datagrams(), 1);
+    }
+
+    #[test]
+    fn test_loopback_pair_latency() {
+        use crate::loopback::LoopbackPair;
+        use crate::{NetworkConditions, NetworkSimulator};
+        use std::thread;
+        use std::time::Duration;
+
+        let mut pair = LoopbackPair::<()>::new(&ENET, 1, ChannelLimit::Maximum).unwrap();
+        let server_id = pair.connect(1, 0).unwrap();
+        pair.step().unwrap();
+
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(20),
+            ..NetworkConditions::default()
+        };
+        pair.server()
+            .set_network_simulator(NetworkSimulator::new(0, conditions));
+
+        let packet = Packet::new(b"delayed", PacketMode::ReliableSequenced).unwrap();
+        pair.client()
+            .peer(server_id)
+            .unwrap()
+            .send_packet(packet, 0)
+            .unwrap();
+
+        // the step does not wait for the delayed datagram
+        let events = pair.step().unwrap();
+        assert!(events.server.is_empty());
+        assert_eq!(
+            pair.server()
+                .network_simulator_mut()
+                .unwrap()
+                .queued_datagrams(),
+            1
+        );
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(5));
+            for event in pair.step().unwrap().server {
+                if let OwnedEvent::Receive { packet, .. } = event {
+                    received.push(packet.data().to_vec());
+                }
+            }
+            if !received.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(received, vec![b"delayed".to_vec()]);
+
+        // the wake-ups of the simulator are not counted
+        let sent = pair.client().traffic_stats().sent_packets;
+        assert_eq!(pair.server().traffic_stats().received_packets, sent);
+    }
+
+    #[test]
+    fn test_simulated_latency() {
+        use crate::{NetworkConditions, NetworkSimulator};
+        use std::time::{Duration, Instant};
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        connect_pair(&mut server, &mut client);
+
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(100),
+            ..NetworkConditions::default()
+        };
+        server.set_network_simulator(NetworkSimulator::new(0, conditions));
+
+        let start = Instant::now();
+        let mut peer = client.peers().next().unwrap();
+        let packet = Packet::new(b"delayed", PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet, 0).unwrap();
+
+        assert_eq!(
+            receive_packet(&mut server, &mut client),
+            Some(b"delayed".to_vec())
+        );
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(
+            server.clear_network_simulator().unwrap().queued_datagrams(),
+            0
+        );
+    }
+
+    /// A writer into a buffer that can still be read after the writer was moved into a `Recorder`.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_replay() {
+        use crate::record::{self, RecordedEvent, Recorder, Replayer};
+
+        let (pcap, events) = (SharedBuffer::default(), SharedBuffer::default());
+        let recorder = Recorder::new()
+            .pcap(pcap.clone())
+            .unwrap()
+            .events(events.clone())
+            .unwrap();
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.set_recorder(recorder);
+        connect_pair(&mut server, &mut client);
+
+        let mut peer = client.peers().next().unwrap();
+        let packet = Packet::new(b"recorded", PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet, 0).unwrap();
+        assert_eq!(
+            receive_packet(&mut server, &mut client),
+            Some(b"recorded".to_vec())
+        );
+        server.take_recorder().unwrap().finish().unwrap();
+
+        let events = record::read_events(&events.0.lock().unwrap()[..]).unwrap();
+        assert!(match events[0].event {
+            RecordedEvent::Connect { .. } => true,
+            _ => false,
+        });
+        assert!(events.iter().any(|record| match record.event {
+            RecordedEvent::Receive { ref data, .. } => data == b"recorded",
+            _ => false,
+        }));
+
+        let datagrams = record::read_pcap(&pcap.0.lock().unwrap()[..]).unwrap();
+        assert!(!datagrams.is_empty());
+        // the client does not listen, so only the server knows its address
+        let client_address = server.peers().next().unwrap().address();
+        assert!(datagrams.iter().all(|d| d.sender == client_address));
+
+        // the recorded client connects to a fresh server, and sends the same packet again
+        let mut replay_server = create_host(true);
+        let mut replayer = Replayer::new(datagrams);
+        let mut received = None;
+
+        for _ in 0..200 {
+            replayer.replay_due(&replay_server.address()).unwrap();
+
+            if let Some(Event::Receive { ref packet, .. }) = replay_server.service(10).unwrap() {
+                received = Some(packet.data().to_vec());
+                break;
+            }
+        }
+
+        assert_eq!(received, Some(b"recorded".to_vec()));
+    }
+
+    #[test]
+    fn test_dissect_loopback() {
+        use crate::protocol::{self, CommandKind};
+        use std::sync::{Arc, Mutex};
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+
+        let datagrams = Arc::new(Mutex::new(Vec::new()));
+        for host in [&mut server, &mut client].iter_mut() {
+            let datagrams = datagrams.clone();
+            host.set_intercept(move |_, data| {
+                datagrams.lock().unwrap().push(data.to_vec());
+                InterceptAction::PassThrough
+            });
+        }
+
+        connect_pair(&mut server, &mut client);
+        let mut peer = client.peers().next().unwrap();
+        let packet = Packet::new(b"dissected", PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet, 1).unwrap();
+        assert_eq!(
+            receive_packet(&mut server, &mut client),
+            Some(b"dissected".to_vec())
+        );
+
+        let commands: Vec<_> = datagrams
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|data| protocol::parse_datagram(data).unwrap().commands)
+            .collect();
+        let names: Vec<_> = commands.iter().map(|c| c.kind.name()).collect();
+
+        assert!(names.contains(&"CONNECT"));
+        assert!(names.contains(&"VERIFY_CONNECT"));
+        assert!(names.contains(&"ACKNOWLEDGE"));
+        assert!(commands.iter().any(|c| c.channel_id == 1
+            && c.kind
+                == CommandKind::SendReliable {
+                    data: b"dissected".to_vec()
+                }));
+    }
+
+    #[test]
+    fn test_range_coder() {
+        use crate::protocol::{CommandKind, Dissector};
+        use crate::RangeCoder;
+        use std::sync::Mutex;
+
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        server.enable_range_coder_compression().unwrap();
+        client.set_compressor(RangeCoder::new());
+
+        let datagrams = Arc::new(Mutex::new(Vec::new()));
+        let received = datagrams.clone();
+        server.set_intercept(move |_, data| {
+            received.lock().unwrap().push(data.to_vec());
+            InterceptAction::PassThrough
+        });
+
+        connect_pair(&mut server, &mut client);
+        let payload = b"range coded, range coded, range coded, range coded".to_vec();
+        let mut peer = client.peers().next().unwrap();
+        let packet = Packet::new(&payload, PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet, 0).unwrap();
+        assert_eq!(
+            receive_packet(&mut server, &mut client),
+            Some(payload.clone())
+        );
+
+        let mut coder = RangeCoder::new();
+        let mut dissector = Dissector::new().decompressor(&mut coder);
+        let datagrams = datagrams.lock().unwrap();
+        let compressed: Vec<_> = datagrams
+            .iter()
+            .map(|data| dissector.parse(data).unwrap())
+            .filter(|datagram| datagram.header.compressed)
+            .collect();
+
+        assert!(compressed
+            .iter()
+            .any(|datagram| datagram.commands.iter().any(|c| c.kind
+                == CommandKind::SendReliable {
+                    data: payload.clone()
+                })));
+    }
+
+    #[test]
+    fn test_spawn_thread() {
+        use std::time::Duration;
+
+        let server = create_host(true);
+        let server_address = server.address();
+        let (handle, events) = server.spawn_thread();
+        let mut client = create_host(false);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        let mut received = None;
+        for _ in 0..200 {
+            if let Some(Event::Receive { ref packet, .. }) = client.service(10).unwrap() {
+                received = Some(packet.data().to_vec());
+                break;
+            }
+
+            match events.recv_timeout(Duration::from_millis(1)) {
+                Ok(OwnedEvent::Connect { peer_id, .. }) => {
+                    let packet = Packet::new(b"threaded", PacketMode::ReliableSequenced).unwrap();
+                    handle.send(peer_id, 0, packet).unwrap();
+
+                    // the client only opened one channel
+                    let packet = Packet::new(b"invalid", PacketMode::ReliableSequenced).unwrap();
+                    match handle.send(peer_id, 1, packet) {
+                        Err(HandleError::Enet(_)) => (),
+                        res => panic!("unexpected result: {:?}", res),
+                    }
+                }
+                Ok(event) => panic!("unexpected event: {:?}", event),
+                Err(_) => (),
+            }
+        }
+
+        assert_eq!(received, Some(b"threaded".to_vec()));
+
+        let client_id = handle
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 1), 1, 0)
+            .unwrap();
+        handle.disconnect(client_id, 0).unwrap();
+    }
+
+    #[test]
+    fn test_spawn_thread_service_failure() {
+        use std::sync::mpsc::RecvTimeoutError;
+        use std::time::Duration;
+
+        let mut server = create_host(true);
+        server.set_intercept(|_, _| InterceptAction::Error);
+        let server_address = server.address();
+        let (handle, events) = server.spawn_thread();
+        let mut client = create_host(false);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        let mut stopped = false;
+        for _ in 0..100 {
+            client.service(10).unwrap();
+            if let Err(RecvTimeoutError::Disconnected) =
+                events.recv_timeout(Duration::from_millis(1))
+            {
+                stopped = true;
+                break;
+            }
+        }
+
+        assert!(stopped);
+        match handle.connect(&server_address, 1, 0) {
+            Err(HandleError::ServiceFailed(_)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[cfg(all(feature = "tokio", unix))]
+    #[test]
+    fn test_next_timer() {
+        let mut server = create_host(true);
+        let mut client = create_host(false);
+        assert_eq!(client.next_timer_ms(), None);
+
+        connect_pair(&mut server, &mut client);
+        // connected peers are pinged after 500ms without traffic
+        assert!(client.next_timer_ms().unwrap() <= 500);
+
+        client.peers().next().unwrap().disconnect_now(0);
+        assert_eq!(client.next_timer_ms(), None);
+    }
+
+    #[cfg(all(feature = "tokio", unix))]
+    #[test]
+    fn test_async_host() {
+        use crate::tokio::AsyncHost;
+        use std::time::Duration;
+
+        let runtime = ::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let mut server = AsyncHost::new(create_host(true)).unwrap();
+            let mut client = AsyncHost::new(create_host(false)).unwrap();
+            client
+                .host_mut()
+                .connect(&server.host().address(), 1, 0)
+                .unwrap();
+            let sender = client.sender();
+
+            let received = async {
+                loop {
+                    ::tokio::select! {
+                        event = server.next_event() => {
+                            if let Event::Receive { ref packet, .. } = event.unwrap() {
+                                return packet.data().to_vec();
+                            }
+                        }
+                        event = client.next_event() => {
+                            if let Event::Connect(_, peer_id, _) = event.unwrap() {
+                                sender
+                                    .send(peer_id, 0, b"async".to_vec(), PacketMode::ReliableSequenced)
+                                    .await
+                                    .unwrap();
+                            }
+                        }
+                    }
+                }
+            };
+
+            let received = ::tokio::time::timeout(Duration::from_secs(5), received)
+                .await
+                .expect("no packet received");
+            assert_eq!(received, b"async");
+        });
     }
 }
@@ -0,0 +1,174 @@
+//! A client and a server `Host` that are serviced in steps, for tests.
+//!
+//! This is not an in-memory transport: ENet always sends through its own UDP socket, so the hosts of a
+//! `LoopbackPair` are bound to the loopback interface, on ports chosen by the operating system. Instead of
+//! servicing both hosts with arbitrary timeouts, tests call `LoopbackPair::step`, which exchanges datagrams until
+//! both hosts have received everything the other one sent. Which events a step returns therefore does not depend
+//! on how long the hosts are serviced:
+//!
+//! ```
+//! use enet::loopback::LoopbackPair;
+//! use enet::{ChannelLimit, Enet, OwnedEvent, Packet, PacketMode};
+//!
+//! let enet = Enet::new().unwrap();
+//! let mut pair = LoopbackPair::<()>::new(&enet, 1, ChannelLimit::Maximum).unwrap();
+//! let server_id = pair.connect(1, 0).unwrap();
+//!
+//! // the handshake completes within a single step
+//! let events = pair.step().unwrap();
+//! assert!(matches!(events.client[..], [OwnedEvent::Connect { .. }]));
+//!
+//! let mut peer = pair.client().peer(server_id).unwrap();
+//! peer.send_packet(Packet::new(b"hello", PacketMode::ReliableSequenced).unwrap(), 0)
+//!     .unwrap();
+//! let events = pair.step().unwrap();
+//! assert!(matches!(events.server[..], [OwnedEvent::Receive { .. }]));
+//! ```
+//!
+//! The results are not fully deterministic, though. A step still waits on the sockets for up to a millisecond at
+//! a time, and ENet's timers, e.g. for resending packets and pings, follow ENet's clock, which is the real time
+//! unless it is moved forward using `time::advance`. As a step takes far less than these timers, they rarely
+//! fire during short tests.
+
+use std::net::Ipv4Addr;
+
+use crate::{
+    Address, BandwidthLimit, ChannelLimit, Enet, Error, Host, OwnedEvent, PeerId, TrafficStats,
+};
+
+/// The maximum time a step waits for datagrams that were sent, but not received yet.
+const STEP_TIMEOUT_MS: u32 = 1000;
+
+/// A client and a server `Host`, connected over the loopback interface and serviced in steps.
+///
+/// All traffic between the hosts should be exchanged through `LoopbackPair::step`.
+pub struct LoopbackPair<T> {
+    server: Host<T>,
+    client: Host<T>,
+}
+
+/// The events that occurred during a `LoopbackPair::step`.
+#[derive(Debug, Default)]
+pub struct LoopbackEvents {
+    /// The events of the server, in the order they occurred.
+    pub server: Vec<OwnedEvent>,
+    /// The events of the client, in the order they occurred.
+    pub client: Vec<OwnedEvent>,
+}
+
+impl<T> LoopbackPair<T> {
+    /// Creates a server, which accepts up to `max_peer_count` peers, and a client. Both hosts are
+    /// created with `channel_limit` and unlimited bandwidth, see `Enet::create_host`.
+    pub fn new(
+        enet: &Enet,
+        max_peer_count: usize,
+        channel_limit: ChannelLimit,
+    ) -> Result<LoopbackPair<T>, Error> {
+        let address = Address::new(Ipv4Addr::LOCALHOST, 0);
+        let create_host = |peer_count| {
+            enet.create_host(
+                Some(&address),
+                peer_count,
+                channel_limit,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+        };
+
+        Ok(LoopbackPair {
+            server: create_host(max_peer_count)?,
+            client: create_host(1)?,
+        })
+    }
+
+    /// Returns the server.
+    pub fn server(&mut self) -> &mut Host<T> {
+        &mut self.server
+    }
+
+    /// Returns the client.
+    pub fn client(&mut self) -> &mut Host<T> {
+        &mut self.client
+    }
+
+    /// Starts connecting the client to the server, see `Host::connect`.
+    ///
+    /// The connection is established during the following steps. Returns the `PeerId` of the server on the client.
+    pub fn connect(&mut self, channel_count: usize, user_data: u32) -> Result<PeerId, Error> {
+        let address = self.server.address();
+
+        Ok(self
+            .client
+            .connect(&address, channel_count, user_data)?
+            .id())
+    }
+
+    /// Sends all queued packets, and services both hosts until each one has received every datagram
+    /// the other one sent, including replies such as acknowledgements. Returns the events of both hosts.
+    ///
+    /// Waits at most about a second for datagrams to arrive, which only happens if the loopback interface
+    /// drops datagrams.
+    pub fn step(&mut self) -> Result<LoopbackEvents, Error> {
+        let mut events = LoopbackEvents::default();
+        let server_start = self.server.traffic_stats();
+        let client_start = self.client.traffic_stats();
+
+        for _ in 0..STEP_TIMEOUT_MS {
+            let sent = (
+                self.server.traffic_stats().sent_packets,
+                self.client.traffic_stats().sent_packets,
+            );
+
+            self.server.flush();
+            self.client.flush();
+
+            let to_server = in_flight(&client_start, &self.client, &server_start, &self.server);
+            let timeout = if to_server > 0 { 1 } else { 0 };
+            self.server.service_batch(timeout, &mut events.server)?;
+
+            let to_client = in_flight(&server_start, &self.server, &client_start, &self.client);
+            let timeout = if to_client > 0 { 1 } else { 0 };
+            self.client.service_batch(timeout, &mut events.client)?;
+
+            let idle = sent
+                == (
+                    self.server.traffic_stats().sent_packets,
+                    self.client.traffic_stats().sent_packets,
+                );
+            if idle
+                && in_flight(&client_start, &self.client, &server_start, &self.server) == 0
+                && in_flight(&server_start, &self.server, &client_start, &self.client) == 0
+            {
+                break;
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+/// Returns the number of datagrams `sender` sent to `receiver` since the start of a step,
+/// which `receiver` has not received yet.
+///
+/// This relies on ENet counting every datagram it reads from its socket in `received_packets`, before the
+/// intercept hook runs. A `NetworkSimulator` therefore has to keep its wake-up datagrams out of these counters,
+/// and a datagram it delays or drops counts as received as soon as it arrives at the socket, so steps do not
+/// wait for it. Since the counters only cover one direction per host, conditions that should apply to both
+/// directions need a simulator on each host.
+fn in_flight<T>(
+    sender_start: &TrafficStats,
+    sender: &Host<T>,
+    receiver_start: &TrafficStats,
+    receiver: &Host<T>,
+) -> u32 {
+    let sent = sender
+        .traffic_stats()
+        .sent_packets
+        .wrapping_sub(sender_start.sent_packets);
+    let received = receiver
+        .traffic_stats()
+        .received_packets
+        .wrapping_sub(receiver_start.received_packets);
+
+    sent.saturating_sub(received)
+}
@@ -0,0 +1,143 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{Error, Packet, PacketMode, Peer};
+
+/// An encoding of serde-serializable messages into packet data. Requires the `serde` feature.
+///
+/// `Peer::send_message` and `Packet::decode` use `JsonCodec`, other encodings can be used through
+/// `Peer::send_message_with` and `Packet::decode_with`. Both ends of a connection must use the same codec.
+///
+/// `JsonCodec` is always available, `BincodeCodec` and `PostcardCodec` require the `bincode` and `postcard`
+/// features. Other serde formats can be plugged in by implementing this trait.
+pub trait Codec {
+    /// Encodes `message` into the data of a packet.
+    fn encode<M: Serialize + ?Sized>(&self, message: &M) -> Result<Vec<u8>, failure::Error>;
+
+    /// Decodes a message from the data of a packet.
+    fn decode<M: DeserializeOwned>(&self, data: &[u8]) -> Result<M, failure::Error>;
+}
+
+/// Encodes messages as JSON, using [serde_json](https://docs.rs/serde_json).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<M: Serialize + ?Sized>(&self, message: &M) -> Result<Vec<u8>, failure::Error> {
+        Ok(serde_json::to_vec(message)?)
+    }
+
+    fn decode<M: DeserializeOwned>(&self, data: &[u8]) -> Result<M, failure::Error> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// Encodes messages using [bincode](https://docs.rs/bincode). Requires the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn encode<M: Serialize + ?Sized>(&self, message: &M) -> Result<Vec<u8>, failure::Error> {
+        Ok(bincode::serialize(message)?)
+    }
+
+    fn decode<M: DeserializeOwned>(&self, data: &[u8]) -> Result<M, failure::Error> {
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+/// Encodes messages using [postcard](https://docs.rs/postcard). Requires the `postcard` feature.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+    fn encode<M: Serialize + ?Sized>(&self, message: &M) -> Result<Vec<u8>, failure::Error> {
+        Ok(postcard::to_allocvec(message)?)
+    }
+
+    fn decode<M: DeserializeOwned>(&self, data: &[u8]) -> Result<M, failure::Error> {
+        Ok(postcard::from_bytes(data)?)
+    }
+}
+
+/// An error that can occur when decoding a message from a `Packet`.
+///
+/// Contains the error reported by the `Codec`.
+#[derive(Fail, Debug)]
+#[fail(display = "could not decode message: {}", _0)]
+pub struct DecodeError(pub failure::Error);
+
+/// An error that can occur when sending a message to a `Peer`.
+#[derive(Fail, Debug)]
+pub enum SendMessageError {
+    /// The message could not be encoded, containing the error reported by the `Codec`.
+    #[fail(display = "could not encode message: {}", _0)]
+    Encode(failure::Error),
+    /// The encoded message could not be sent.
+    #[fail(display = "{}", _0)]
+    Enet(#[cause] Error),
+}
+
+impl Packet {
+    /// Creates a new Packet containing `message`, encoded using `codec`.
+    pub fn encode_with<C, M>(
+        codec: &C,
+        message: &M,
+        mode: PacketMode,
+    ) -> Result<Packet, SendMessageError>
+    where
+        C: Codec,
+        M: Serialize + ?Sized,
+    {
+        let data = codec.encode(message).map_err(SendMessageError::Encode)?;
+
+        Packet::from_vec(data, mode).map_err(SendMessageError::Enet)
+    }
+
+    /// Decodes the message contained in this packet, using `JsonCodec`.
+    pub fn decode<M: DeserializeOwned>(&self) -> Result<M, DecodeError> {
+        self.decode_with(&JsonCodec)
+    }
+
+    /// Decodes the message contained in this packet, using `codec`.
+    pub fn decode_with<C: Codec, M: DeserializeOwned>(&self, codec: &C) -> Result<M, DecodeError> {
+        codec.decode(self.data()).map_err(DecodeError)
+    }
+}
+
+impl<'a, T> Peer<'a, T> {
+    /// Queues `message` to be sent to this peer on `channel_id`, encoded using `JsonCodec`.
+    pub fn send_message<M>(
+        &mut self,
+        message: &M,
+        channel_id: u8,
+        mode: PacketMode,
+    ) -> Result<(), SendMessageError>
+    where
+        M: Serialize + ?Sized,
+    {
+        self.send_message_with(&JsonCodec, message, channel_id, mode)
+    }
+
+    /// Queues `message` to be sent to this peer on `channel_id`, encoded using `codec`.
+    pub fn send_message_with<C, M>(
+        &mut self,
+        codec: &C,
+        message: &M,
+        channel_id: u8,
+        mode: PacketMode,
+    ) -> Result<(), SendMessageError>
+    where
+        C: Codec,
+        M: Serialize + ?Sized,
+    {
+        let packet = Packet::encode_with(codec, message, mode)?;
+
+        self.send_packet(packet, channel_id)
+            .map_err(SendMessageError::Enet)
+    }
+}
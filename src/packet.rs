@@ -1,5 +1,11 @@
+use std::any::Any;
+
+use bitflags::bitflags;
+
 use enet_sys::{
-    enet_packet_create, enet_packet_destroy, ENetPacket, _ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE,
+    enet_packet_create, enet_packet_destroy, ENetPacket,
+    _ENetPacketFlag_ENET_PACKET_FLAG_NO_ALLOCATE, _ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE,
+    _ENetPacketFlag_ENET_PACKET_FLAG_SENT, _ENetPacketFlag_ENET_PACKET_FLAG_UNRELIABLE_FRAGMENT,
     _ENetPacketFlag_ENET_PACKET_FLAG_UNSEQUENCED,
 };
 
@@ -11,6 +17,29 @@ pub struct Packet {
     inner: *mut ENetPacket,
 }
 
+// A `Packet` is the only owner of its `ENetPacket` until it is handed to ENet, and buffers passed to
+// `Packet::from_buffer` must be `Send`, so it can be moved to another thread.
+unsafe impl Send for Packet {}
+
+bitflags! {
+    /// The flags of a packet, as used by ENet.
+    ///
+    /// Usually, `PacketMode` is used to choose how a packet is delivered. These flags additionally
+    /// include ENet's internal state of a packet, e.g. whether it has already been sent.
+    pub struct PacketFlags: u32 {
+        /// The packet must be received by the target peer, and resend attempts are made until it is.
+        const RELIABLE = _ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE as u32;
+        /// The packet will not be sequenced with other packets.
+        const UNSEQUENCED = _ENetPacketFlag_ENET_PACKET_FLAG_UNSEQUENCED as u32;
+        /// The packet does not own its data, see `Packet::from_buffer`.
+        const NO_ALLOCATE = _ENetPacketFlag_ENET_PACKET_FLAG_NO_ALLOCATE as u32;
+        /// The packet will be fragmented using unreliable (instead of reliable) sends if it exceeds the MTU.
+        const UNRELIABLE_FRAGMENT = _ENetPacketFlag_ENET_PACKET_FLAG_UNRELIABLE_FRAGMENT as u32;
+        /// The packet has been sent from all queues it has been entered into.
+        const SENT = _ENetPacketFlag_ENET_PACKET_FLAG_SENT as u32;
+    }
+}
+
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 /// Mode that can be set when transmitting a packet.
 ///
@@ -22,6 +51,10 @@ pub enum PacketMode {
     UnreliableUnsequenced,
     /// The packet will be sent reliably and sequenced with other reliable packets.
     ReliableSequenced,
+    /// The packet will be sent unreliably but sequenced, like `UnreliableSequenced`.
+    ///
+    /// If the packet exceeds the MTU, its fragments will be sent unreliably as well, instead of being sent reliably.
+    UnreliableFragmented,
 }
 
 impl PacketMode {
@@ -31,6 +64,7 @@ impl PacketMode {
             PacketMode::UnreliableSequenced => false,
             PacketMode::UnreliableUnsequenced => false,
             PacketMode::ReliableSequenced => true,
+            PacketMode::UnreliableFragmented => false,
         }
     }
 
@@ -40,25 +74,32 @@ impl PacketMode {
             PacketMode::UnreliableSequenced => true,
             PacketMode::UnreliableUnsequenced => false,
             PacketMode::ReliableSequenced => true,
+            PacketMode::UnreliableFragmented => true,
         }
     }
 
-    const UNSEQUENCED_FLAG: u32 = _ENetPacketFlag_ENET_PACKET_FLAG_UNSEQUENCED as u32;
-    const RELIABLE_FLAG: u32 = _ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE as u32;
-    fn to_sys_flags(&self) -> u32 {
+    /// Returns the packet flags corresponding to this mode.
+    pub fn to_flags(&self) -> PacketFlags {
         match self {
-            PacketMode::UnreliableSequenced => 0,
-            PacketMode::UnreliableUnsequenced => PacketMode::UNSEQUENCED_FLAG,
-            PacketMode::ReliableSequenced => PacketMode::RELIABLE_FLAG,
+            PacketMode::UnreliableSequenced => PacketFlags::empty(),
+            PacketMode::UnreliableUnsequenced => PacketFlags::UNSEQUENCED,
+            PacketMode::ReliableSequenced => PacketFlags::RELIABLE,
+            PacketMode::UnreliableFragmented => PacketFlags::UNRELIABLE_FRAGMENT,
         }
     }
 
-    fn from_sys_flags(sys_flags: u32) -> PacketMode {
-        match sys_flags {
-            0 => PacketMode::UnreliableSequenced,
-            PacketMode::UNSEQUENCED_FLAG => PacketMode::UnreliableUnsequenced,
-            PacketMode::RELIABLE_FLAG => PacketMode::ReliableSequenced,
-            _ => panic!("Invalid sysflag"),
+    /// Returns the mode a packet with the given flags is delivered with.
+    ///
+    /// Like in ENet, reliability takes precedence over the other flags.
+    pub fn from_flags(flags: PacketFlags) -> PacketMode {
+        if flags.contains(PacketFlags::RELIABLE) {
+            PacketMode::ReliableSequenced
+        } else if flags.contains(PacketFlags::UNSEQUENCED) {
+            PacketMode::UnreliableUnsequenced
+        } else if flags.contains(PacketFlags::UNRELIABLE_FRAGMENT) {
+            PacketMode::UnreliableFragmented
+        } else {
+            PacketMode::UnreliableSequenced
         }
     }
 
@@ -68,6 +109,7 @@ impl PacketMode {
             "unreliable" => Some(PacketMode::UnreliableSequenced),
             "unsequenced" => Some(PacketMode::UnreliableUnsequenced),
             "reliable" => Some(PacketMode::ReliableSequenced),
+            "unreliable_fragmented" => Some(PacketMode::UnreliableFragmented),
             _ => None,
         }
     }
@@ -77,13 +119,64 @@ impl Packet {
     /// Creates a new Packet with optional reliability settings.
     pub fn new(data: &[u8], mode: PacketMode) -> Result<Packet, Error> {
         let res = unsafe {
-            enet_packet_create(data.as_ptr() as *const _, data.len(), mode.to_sys_flags())
+            enet_packet_create(
+                data.as_ptr() as *const _,
+                data.len(),
+                mode.to_flags().bits(),
+            )
+        };
+
+        if res.is_null() {
+            return Err(Error(0));
+        }
+
+        Ok(Packet::from_sys_packet(res))
+    }
+
+    /// Creates a new Packet that takes ownership of `data`, without copying it. See `Packet::from_buffer`.
+    pub fn from_vec(data: Vec<u8>, mode: PacketMode) -> Result<Packet, Error> {
+        Packet::from_buffer(data, mode)
+    }
+
+    /// Creates a new Packet that takes ownership of `data`, without copying it. See `Packet::from_buffer`.
+    #[cfg(feature = "bytes")]
+    pub fn from_bytes(data: bytes::Bytes, mode: PacketMode) -> Result<Packet, Error> {
+        Packet::from_buffer(data, mode)
+    }
+
+    /// Creates a new Packet that takes ownership of an arbitrary buffer, without copying it.
+    ///
+    /// The buffer is dropped once ENet does not need the packet anymore.
+    pub fn from_buffer<B>(buffer: B, mode: PacketMode) -> Result<Packet, Error>
+    where
+        B: AsRef<[u8]> + Send + 'static,
+    {
+        // Box the buffer before taking a pointer to its contents, because some buffers (e.g. small `Bytes`)
+        // store their data inline, which would be invalidated by moving them.
+        let buffer = Box::new(buffer);
+        let (data, data_len) = {
+            let data = (*buffer).as_ref();
+            (data.as_ptr(), data.len())
+        };
+        let owner: Box<Box<dyn Any + Send>> = Box::new(buffer);
+
+        let res = unsafe {
+            enet_packet_create(
+                data as *const _,
+                data_len,
+                (mode.to_flags() | PacketFlags::NO_ALLOCATE).bits(),
+            )
         };
 
         if res.is_null() {
             return Err(Error(0));
         }
 
+        unsafe {
+            (*res).userData = Box::into_raw(owner) as *mut _;
+            (*res).freeCallback = Some(free_owned_buffer);
+        }
+
         Ok(Packet::from_sys_packet(res))
     }
 
@@ -111,7 +204,22 @@ impl Packet {
 
     /// Returns the delivery mechanism for this packet.
     pub fn packet_mode<'a>(&'a self) -> PacketMode {
-        unsafe { PacketMode::from_sys_flags((*self.inner).flags) }
+        PacketMode::from_flags(self.flags())
+    }
+
+    /// Returns all flags of this packet.
+    pub fn flags(&self) -> PacketFlags {
+        PacketFlags::from_bits_truncate(unsafe { (*self.inner).flags })
+    }
+}
+
+/// Drops the buffer owned by a packet created through `Packet::from_buffer`.
+unsafe extern "C" fn free_owned_buffer(packet: *mut ENetPacket) {
+    let owner = (*packet).userData as *mut Box<dyn Any + Send>;
+
+    if !owner.is_null() {
+        let _: Box<Box<dyn Any + Send>> = Box::from_raw(owner);
+        (*packet).userData = std::ptr::null_mut();
     }
 }
 
@@ -122,3 +230,36 @@ impl Drop for Packet {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PacketFlags, PacketMode};
+
+    #[test]
+    fn test_mode_flags_roundtrip() {
+        for mode in &[
+            PacketMode::UnreliableSequenced,
+            PacketMode::UnreliableUnsequenced,
+            PacketMode::ReliableSequenced,
+            PacketMode::UnreliableFragmented,
+        ] {
+            assert_eq!(PacketMode::from_flags(mode.to_flags()), *mode);
+        }
+    }
+
+    #[test]
+    fn test_mode_from_unknown_flags() {
+        assert_eq!(
+            PacketMode::from_flags(PacketFlags::NO_ALLOCATE | PacketFlags::SENT),
+            PacketMode::UnreliableSequenced
+        );
+        assert_eq!(
+            PacketMode::from_flags(PacketFlags::RELIABLE | PacketFlags::UNSEQUENCED),
+            PacketMode::ReliableSequenced
+        );
+        assert_eq!(
+            PacketMode::from_flags(PacketFlags::from_bits_truncate(0xffff_ffff)),
+            PacketMode::ReliableSequenced
+        );
+    }
+}
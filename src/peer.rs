@@ -14,7 +14,8 @@ use enet_sys::{
     _ENetPeerState_ENET_PEER_STATE_DISCONNECT_LATER, _ENetPeerState_ENET_PEER_STATE_ZOMBIE,
 };
 
-use crate::{Address, Error, Packet};
+use crate::checksum::{ActiveChecksumGuard, ChecksumFn};
+use crate::{Address, Channel, Error, Packet};
 
 /// This struct represents an endpoint in an ENet-connection.
 ///
@@ -26,10 +27,38 @@ use crate::{Address, Error, Packet};
 #[derive(Clone, Debug)]
 pub struct Peer<'a, T: 'a> {
     inner: *mut ENetPeer,
+    /// The custom checksum of the peer's `Host`, for datagrams sent through this `Peer`.
+    checksum: Option<*const ChecksumFn>,
 
     _data: PhantomData<&'a mut T>,
 }
 
+/// A stable handle to a `Peer`, which can be stored and used to look up the peer later on.
+///
+/// Consists of the index of the peer in its `Host`, and the id ENet assigned to the current connection.
+/// Once the connection ends, the index may be reused for a new connection, which gets a new `PeerId`.
+/// See `Host::peer`.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PeerId {
+    index: usize,
+    connect_id: u32,
+}
+
+impl PeerId {
+    pub(crate) fn new(index: usize, connect_id: u32) -> PeerId {
+        PeerId { index, connect_id }
+    }
+
+    /// Returns the index of the peer in its `Host`.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub(crate) fn connect_id(&self) -> u32 {
+        self.connect_id
+    }
+}
+
 /// A packet received directly from a `Peer`.
 ///
 /// Contains the received packet as well as the channel on which it was received.
@@ -43,6 +72,54 @@ pub struct PeerPacket<'b, 'a, T: 'a> {
     _priv_guard: PhantomData<&'b Peer<'a, T>>,
 }
 
+/// A snapshot of the network statistics ENet tracks for a `Peer`.
+///
+/// Returned by `Peer::stats`. Packet loss values are averaged by ENet over intervals of 10 seconds,
+/// the packet counters are reset at the end of each interval.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PeerStats {
+    /// Mean round trip time between sending a reliable packet and receiving its acknowledgement.
+    pub round_trip_time: Duration,
+    /// Variance of the mean round trip time.
+    pub round_trip_time_variance: Duration,
+    /// Round trip time of the last acknowledged reliable packet.
+    pub last_round_trip_time: Duration,
+    /// Lowest round trip time measured.
+    pub lowest_round_trip_time: Duration,
+    /// Mean ratio of lost reliable packets, between 0 and 1.
+    pub packet_loss: f32,
+    /// Variance of the mean packet loss ratio.
+    pub packet_loss_variance: f32,
+    /// Number of reliable packets sent in the current packet loss interval.
+    pub packets_sent: u32,
+    /// Number of reliable packets lost in the current packet loss interval.
+    pub packets_lost: u32,
+    /// Current throttle of unreliable packets, between 0 (all dropped) and 32 (none dropped).
+    pub packet_throttle: u32,
+    /// Upper limit of the packet throttle.
+    pub packet_throttle_limit: u32,
+    /// Rate at which the packet throttle increases when conditions are good.
+    pub packet_throttle_acceleration: u32,
+    /// Rate at which the packet throttle decreases when conditions are bad.
+    pub packet_throttle_deceleration: u32,
+    /// Interval over which the packet throttle is measured.
+    pub packet_throttle_interval: Duration,
+    /// Maximum transmission unit of the connection, in bytes.
+    pub mtu: u32,
+    /// Size of the reliable window, in bytes.
+    pub window_size: u32,
+    /// Amount of reliable data sent but not yet acknowledged, in bytes.
+    pub reliable_data_in_transit: u32,
+    /// Data received in the current bandwidth interval, in bytes.
+    pub incoming_data_total: u32,
+    /// Data sent in the current bandwidth interval, in bytes.
+    pub outgoing_data_total: u32,
+    /// ENet timestamp (in milliseconds) of the last packet sent to this peer.
+    pub last_send_time: u32,
+    /// ENet timestamp (in milliseconds) of the last packet received from this peer.
+    pub last_receive_time: u32,
+}
+
 /// Describes the state a `Peer` is in.
 ///
 /// The states should be self-explanatory, ENet doesn't explain them more either.
@@ -62,7 +139,7 @@ pub enum PeerState {
 }
 
 impl PeerState {
-    fn from_sys_state(enet_sys_state: _ENetPeerState) -> PeerState {
+    pub(crate) fn from_sys_state(enet_sys_state: _ENetPeerState) -> PeerState {
         #[allow(non_upper_case_globals)]
         match enet_sys_state {
             _ENetPeerState_ENET_PEER_STATE_DISCONNECTED => PeerState::Disconnected,
@@ -83,13 +160,31 @@ impl PeerState {
 }
 
 impl<'a, T> Peer<'a, T> {
-    pub(crate) fn new(inner: *mut ENetPeer) -> Peer<'a, T> {
+    pub(crate) fn new(inner: *mut ENetPeer, checksum: Option<*const ChecksumFn>) -> Peer<'a, T> {
         Peer {
             inner,
+            checksum,
             _data: PhantomData,
         }
     }
 
+    pub(crate) fn as_raw(&self) -> *mut ENetPeer {
+        self.inner
+    }
+
+    /// Returns the index of this `Peer` in its `Host`.
+    pub(crate) fn index(&self) -> usize {
+        // ENet uses the index of a peer as its incoming peer id
+        unsafe { (*self.inner).incomingPeerID as usize }
+    }
+
+    /// Returns the id of the current connection of this `Peer`.
+    ///
+    /// The id is only meaningful while the peer is connected, or trying to connect.
+    pub fn id(&self) -> PeerId {
+        PeerId::new(self.index(), unsafe { (*self.inner).connectID })
+    }
+
     /// Returns the address of this `Peer`.
     pub fn address(&self) -> Address {
         Address::from_enet_address(&unsafe { (*self.inner).address })
@@ -160,6 +255,38 @@ impl<'a, T> Peer<'a, T> {
         Duration::from_millis(unsafe { (*self.inner).roundTripTime } as u64)
     }
 
+    /// Returns a snapshot of the network statistics of this `Peer`.
+    pub fn stats(&self) -> PeerStats {
+        // ENet scales packet loss values by this factor (`ENET_PEER_PACKET_LOSS_SCALE`).
+        const PACKET_LOSS_SCALE: f32 = 65536.0;
+
+        let peer = unsafe { &*self.inner };
+        let millis = |ms: u32| Duration::from_millis(u64::from(ms));
+
+        PeerStats {
+            round_trip_time: millis(peer.roundTripTime),
+            round_trip_time_variance: millis(peer.roundTripTimeVariance),
+            last_round_trip_time: millis(peer.lastRoundTripTime),
+            lowest_round_trip_time: millis(peer.lowestRoundTripTime),
+            packet_loss: peer.packetLoss as f32 / PACKET_LOSS_SCALE,
+            packet_loss_variance: peer.packetLossVariance as f32 / PACKET_LOSS_SCALE,
+            packets_sent: peer.packetsSent,
+            packets_lost: peer.packetsLost,
+            packet_throttle: peer.packetThrottle,
+            packet_throttle_limit: peer.packetThrottleLimit,
+            packet_throttle_acceleration: peer.packetThrottleAcceleration,
+            packet_throttle_deceleration: peer.packetThrottleDeceleration,
+            packet_throttle_interval: millis(peer.packetThrottleInterval),
+            mtu: peer.mtu,
+            window_size: peer.windowSize,
+            reliable_data_in_transit: peer.reliableDataInTransit,
+            incoming_data_total: peer.incomingDataTotal,
+            outgoing_data_total: peer.outgoingDataTotal,
+            last_send_time: peer.lastSendTime,
+            last_receive_time: peer.lastReceiveTime,
+        }
+    }
+
     /// Forcefully disconnects this `Peer`.
     ///
     /// The foreign host represented by the peer is not notified of the disconnection and will timeout on its connection to the local host.
@@ -188,6 +315,15 @@ impl<'a, T> Peer<'a, T> {
         }
     }
 
+    /// Queues `data` to be sent on `channel`, using the default `PacketMode` of the channel.
+    ///
+    /// See `ChannelLayout`.
+    pub fn send_on(&mut self, channel: &Channel, data: &[u8]) -> Result<(), Error> {
+        let packet = Packet::new(data, channel.mode())?;
+
+        self.send_packet(packet, channel.id())
+    }
+
     /// Disconnects from this peer.
     ///
     /// A `Disconnect` event will be returned by `Host::service` once the disconnection is complete.
@@ -202,6 +338,8 @@ impl<'a, T> Peer<'a, T> {
     /// No `Disconnect` event will be created. No disconnect notification for the foreign peer is guaranteed, and this `Peer` is immediately reset on return from this method.
     pub fn disconnect_now(self, user_data: u32) {
         unsafe {
+            // the disconnect notification is sent right away, outside of `Host::service`
+            let _checksum = ActiveChecksumGuard::new(self.checksum.map(|f| &*f));
             enet_peer_disconnect_now(self.inner, user_data);
         }
     }
@@ -0,0 +1,864 @@
+//! Parsing of raw ENet datagrams, e.g. from a capture made with a `record::Recorder`.
+//!
+//! A datagram consists of a protocol header, an optional checksum, and a list of commands, which may be
+//! compressed. `parse_datagram` handles datagrams of hosts without checksums and compression, a `Dissector`
+//! can be configured for the others. All parsed types implement `Display`, which prints them in a compact,
+//! human-readable form:
+//!
+//! ```
+//! let datagram = enet::protocol::parse_datagram(&[
+//!     0x00, 0x00, // peer 0, session 0, without sent time
+//!     0x05, 0xff, 0x00, 0x02, // PING on the control channel, reliable sequence number 2
+//! ])
+//! .unwrap();
+//!
+//! assert_eq!(
+//!     datagram.to_string(),
+//!     "peer 0, session 0\n  PING channel 255 seq 2"
+//! );
+//! ```
+//!
+//! The layout of datagrams follows ENet's `protocol.h`. All fields are in network byte order, except for
+//! connect ids and checksums, which ENet sends in the byte order of the sending machine. They are read in
+//! the byte order of this machine.
+
+use std::fmt;
+
+use crate::Compressor;
+
+const HEADER_FLAG_COMPRESSED: u16 = 1 << 14;
+const HEADER_FLAG_SENT_TIME: u16 = 1 << 15;
+const HEADER_SESSION_MASK: u16 = 3 << 12;
+const HEADER_SESSION_SHIFT: u16 = 12;
+const HEADER_PEER_ID_MASK: u16 = 0x0fff;
+/// The peer id of datagrams that are not sent to a peer yet, i.e. those carrying a `CONNECT`.
+const NO_PEER_ID: u16 = 0x0fff;
+
+const COMMAND_MASK: u8 = 0x0f;
+const COMMAND_FLAG_ACKNOWLEDGE: u8 = 1 << 7;
+const COMMAND_FLAG_UNSEQUENCED: u8 = 1 << 6;
+
+const COMMAND_ACKNOWLEDGE: u8 = 1;
+const COMMAND_CONNECT: u8 = 2;
+const COMMAND_VERIFY_CONNECT: u8 = 3;
+const COMMAND_DISCONNECT: u8 = 4;
+const COMMAND_PING: u8 = 5;
+const COMMAND_SEND_RELIABLE: u8 = 6;
+const COMMAND_SEND_UNRELIABLE: u8 = 7;
+const COMMAND_SEND_FRAGMENT: u8 = 8;
+const COMMAND_SEND_UNSEQUENCED: u8 = 9;
+const COMMAND_BANDWIDTH_LIMIT: u8 = 10;
+const COMMAND_THROTTLE_CONFIGURE: u8 = 11;
+const COMMAND_SEND_UNRELIABLE_FRAGMENT: u8 = 12;
+
+/// The largest datagram ENet sends, used to size the buffer for decompression.
+const MAXIMUM_MTU: usize = 4096;
+
+/// The number of payload bytes printed by `Display` implementations, before the rest is elided.
+const DISPLAYED_DATA_LEN: usize = 16;
+
+/// An error that can occur when parsing a datagram.
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The datagram ended in the middle of a header or command.
+    #[fail(display = "datagram is truncated at offset {}", _0)]
+    Truncated(usize),
+    /// The datagram contains a command ENet does not know.
+    #[fail(display = "unknown command {} at offset {}", command, offset)]
+    UnknownCommand {
+        /// The command number.
+        command: u8,
+        /// The offset of the command in the (decompressed) datagram.
+        offset: usize,
+    },
+    /// The datagram is compressed, but the `Dissector` has no compressor.
+    #[fail(display = "datagram is compressed")]
+    Compressed,
+    /// The compressor could not decompress the datagram.
+    #[fail(display = "datagram could not be decompressed")]
+    Decompress,
+}
+
+/// Parses datagrams of hosts with the given checksum and compression settings.
+///
+/// ```
+/// use enet::protocol::Dissector;
+///
+/// // hosts using `Host::set_checksum` prefix the commands with a 4 byte checksum
+/// let mut dissector = Dissector::new().checksum(true);
+/// let datagram = dissector
+///     .parse(&[0x00, 0x00, 0x12, 0x34, 0x56, 0x78, 0x05, 0xff, 0x00, 0x02])
+///     .unwrap();
+///
+/// assert_eq!(datagram.checksum, Some(u32::from_ne_bytes([0x12, 0x34, 0x56, 0x78])));
+/// assert_eq!(datagram.commands.len(), 1);
+/// ```
+#[derive(Default)]
+pub struct Dissector<'a> {
+    checksum: bool,
+    decompressor: Option<&'a mut dyn Compressor>,
+}
+
+/// A parsed ENet datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    /// The protocol header.
+    pub header: Header,
+    /// The checksum of the datagram, if the `Dissector` expected one. It is not verified, as it is computed
+    /// with the connect id of the connection.
+    pub checksum: Option<u32>,
+    /// The commands of the datagram, after decompression.
+    pub commands: Vec<Command>,
+}
+
+/// The protocol header of a datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// The index of the receiving peer on the receiving host, or `None` for datagrams sent before
+    /// the connection is established.
+    pub peer_id: Option<u16>,
+    /// The session id of the connection, which distinguishes reconnections on the same peer.
+    pub session_id: u8,
+    /// Whether the commands are compressed.
+    pub compressed: bool,
+    /// The time the datagram was sent at, in milliseconds, truncated to 16 bits.
+    ///
+    /// Only present on datagrams containing commands that need to be acknowledged.
+    pub sent_time: Option<u16>,
+}
+
+/// A command in a datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// The channel the command belongs to, 255 for commands that belong to the connection.
+    pub channel_id: u8,
+    /// The reliable sequence number of the command on its channel.
+    pub reliable_sequence_number: u16,
+    /// Whether the receiver has to acknowledge the command.
+    pub acknowledge: bool,
+    /// Whether the command is sent unsequenced.
+    pub unsequenced: bool,
+    /// The type of the command, and its fields.
+    pub kind: CommandKind,
+}
+
+/// The type of a `Command`, and its fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandKind {
+    /// Acknowledges a command that was received.
+    Acknowledge {
+        /// The reliable sequence number of the acknowledged command.
+        received_reliable_sequence_number: u16,
+        /// The sent time of the datagram containing the acknowledged command.
+        received_sent_time: u16,
+    },
+    /// Requests a connection, sent by `Host::connect`.
+    Connect {
+        /// The parameters of the connection.
+        parameters: ConnectParameters,
+        /// The user-specified data passed to `Host::connect`.
+        data: u32,
+    },
+    /// Accepts a connection.
+    VerifyConnect(ConnectParameters),
+    /// Ends a connection.
+    Disconnect {
+        /// The user-specified data of the disconnection.
+        data: u32,
+    },
+    /// Keeps a connection alive.
+    Ping,
+    /// A reliable packet.
+    SendReliable {
+        /// The contents of the packet.
+        data: Vec<u8>,
+    },
+    /// An unreliable, sequenced packet.
+    SendUnreliable {
+        /// The unreliable sequence number of the packet.
+        unreliable_sequence_number: u16,
+        /// The contents of the packet.
+        data: Vec<u8>,
+    },
+    /// A fragment of a reliable packet.
+    SendFragment(Fragment),
+    /// An unsequenced packet.
+    SendUnsequenced {
+        /// The unsequenced group of the packet, used to drop duplicates.
+        unsequenced_group: u16,
+        /// The contents of the packet.
+        data: Vec<u8>,
+    },
+    /// Announces a change of the bandwidth limits of the sender, see `Host::set_bandwith_limits`.
+    BandwidthLimit {
+        /// The incoming bandwidth of the sender, in bytes per second. 0 means unlimited.
+        incoming_bandwidth: u32,
+        /// The outgoing bandwidth of the sender, in bytes per second. 0 means unlimited.
+        outgoing_bandwidth: u32,
+    },
+    /// Configures the packet throttle of the receiver.
+    ThrottleConfigure {
+        /// The interval over which the throttle is measured, in milliseconds.
+        interval: u32,
+        /// The rate at which the throttle increases.
+        acceleration: u32,
+        /// The rate at which the throttle decreases.
+        deceleration: u32,
+    },
+    /// A fragment of an unreliable packet.
+    SendUnreliableFragment(Fragment),
+}
+
+/// The parameters of a connection, sent in `Connect` and `VerifyConnect` commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectParameters {
+    /// The index of the peer on the sending host, which the receiver uses as `Header::peer_id`.
+    pub outgoing_peer_id: u16,
+    /// The session id the sender expects on incoming datagrams.
+    pub incoming_session_id: u8,
+    /// The session id the sender uses on outgoing datagrams.
+    pub outgoing_session_id: u8,
+    /// The maximum transmission unit of the sender.
+    pub mtu: u32,
+    /// The window size of the sender, in bytes.
+    pub window_size: u32,
+    /// The number of channels of the connection.
+    pub channel_count: u32,
+    /// The incoming bandwidth of the sender, in bytes per second. 0 means unlimited.
+    pub incoming_bandwidth: u32,
+    /// The outgoing bandwidth of the sender, in bytes per second. 0 means unlimited.
+    pub outgoing_bandwidth: u32,
+    /// The interval over which the packet throttle is measured, in milliseconds.
+    pub packet_throttle_interval: u32,
+    /// The rate at which the packet throttle increases.
+    pub packet_throttle_acceleration: u32,
+    /// The rate at which the packet throttle decreases.
+    pub packet_throttle_deceleration: u32,
+    /// The random id of the connection.
+    pub connect_id: u32,
+}
+
+/// A fragment of a packet that is too large for a single datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    /// The sequence number of the first fragment, which identifies the packet.
+    pub start_sequence_number: u16,
+    /// The number of fragments of the packet.
+    pub fragment_count: u32,
+    /// The index of this fragment.
+    pub fragment_number: u32,
+    /// The length of the whole packet.
+    pub total_length: u32,
+    /// The offset of this fragment in the packet.
+    pub fragment_offset: u32,
+    /// The contents of this fragment.
+    pub data: Vec<u8>,
+}
+
+/// Parses a datagram of a host without checksums and compression.
+pub fn parse_datagram(data: &[u8]) -> Result<Datagram, ProtocolError> {
+    Dissector::new().parse(data)
+}
+
+impl<'a> Dissector<'a> {
+    /// Creates a dissector for hosts without checksums and compression.
+    pub fn new() -> Dissector<'a> {
+        Dissector::default()
+    }
+
+    /// Sets whether datagrams carry a checksum, i.e. whether the sending host uses `Host::set_checksum`.
+    pub fn checksum(mut self, checksum: bool) -> Dissector<'a> {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets the compressor used to decompress compressed datagrams, which has to match the one
+    /// installed on the sending host with `Host::set_compressor`, or be a `RangeCoder` for hosts
+    /// using `Host::enable_range_coder_compression`.
+    pub fn decompressor(mut self, decompressor: &'a mut dyn Compressor) -> Dissector<'a> {
+        self.decompressor = Some(decompressor);
+        self
+    }
+
+    /// Parses `data`, the UDP payload of a datagram.
+    pub fn parse(&mut self, data: &[u8]) -> Result<Datagram, ProtocolError> {
+        let mut reader = Reader::new(data);
+
+        let peer_id = reader.u16()?;
+        let sent_time = if peer_id & HEADER_FLAG_SENT_TIME != 0 {
+            Some(reader.u16()?)
+        } else {
+            None
+        };
+        let header = Header {
+            peer_id: match peer_id & HEADER_PEER_ID_MASK {
+                NO_PEER_ID => None,
+                id => Some(id),
+            },
+            session_id: ((peer_id & HEADER_SESSION_MASK) >> HEADER_SESSION_SHIFT) as u8,
+            compressed: peer_id & HEADER_FLAG_COMPRESSED != 0,
+            sent_time,
+        };
+
+        let checksum = if self.checksum {
+            let bytes = reader.bytes(4)?;
+            Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        } else {
+            None
+        };
+
+        let commands = if header.compressed {
+            let decompressor = self
+                .decompressor
+                .as_mut()
+                .ok_or(ProtocolError::Compressed)?;
+            let mut decompressed = vec![0; MAXIMUM_MTU];
+            let len = decompressor
+                .decompress(reader.rest(), &mut decompressed)
+                .filter(|&len| len <= decompressed.len())
+                .ok_or(ProtocolError::Decompress)?;
+
+            // offsets in errors refer to the datagram as if it was never compressed
+            let mut commands = Reader::new(&decompressed[..len]);
+            commands.base = reader.offset();
+            parse_commands(commands)?
+        } else {
+            parse_commands(reader)?
+        };
+
+        Ok(Datagram {
+            header,
+            checksum,
+            commands,
+        })
+    }
+}
+
+impl fmt::Debug for Dissector<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dissector")
+            .field("checksum", &self.checksum)
+            .field("decompressor", &self.decompressor.is_some())
+            .finish()
+    }
+}
+
+fn parse_commands(mut reader: Reader<'_>) -> Result<Vec<Command>, ProtocolError> {
+    let mut commands = Vec::new();
+
+    while !reader.rest().is_empty() {
+        let offset = reader.offset();
+        let command = reader.u8()?;
+        let channel_id = reader.u8()?;
+        let reliable_sequence_number = reader.u16()?;
+
+        let kind = match command & COMMAND_MASK {
+            COMMAND_ACKNOWLEDGE => CommandKind::Acknowledge {
+                received_reliable_sequence_number: reader.u16()?,
+                received_sent_time: reader.u16()?,
+            },
+            COMMAND_CONNECT => CommandKind::Connect {
+                parameters: parse_connect_parameters(&mut reader)?,
+                data: reader.u32()?,
+            },
+            COMMAND_VERIFY_CONNECT => {
+                CommandKind::VerifyConnect(parse_connect_parameters(&mut reader)?)
+            }
+            COMMAND_DISCONNECT => CommandKind::Disconnect {
+                data: reader.u32()?,
+            },
+            COMMAND_PING => CommandKind::Ping,
+            COMMAND_SEND_RELIABLE => {
+                let len = reader.u16()?;
+                CommandKind::SendReliable {
+                    data: reader.bytes(usize::from(len))?.to_vec(),
+                }
+            }
+            COMMAND_SEND_UNRELIABLE => {
+                let unreliable_sequence_number = reader.u16()?;
+                let len = reader.u16()?;
+                CommandKind::SendUnreliable {
+                    unreliable_sequence_number,
+                    data: reader.bytes(usize::from(len))?.to_vec(),
+                }
+            }
+            COMMAND_SEND_FRAGMENT => CommandKind::SendFragment(parse_fragment(&mut reader)?),
+            COMMAND_SEND_UNSEQUENCED => {
+                let unsequenced_group = reader.u16()?;
+                let len = reader.u16()?;
+                CommandKind::SendUnsequenced {
+                    unsequenced_group,
+                    data: reader.bytes(usize::from(len))?.to_vec(),
+                }
+            }
+            COMMAND_BANDWIDTH_LIMIT => CommandKind::BandwidthLimit {
+                incoming_bandwidth: reader.u32()?,
+                outgoing_bandwidth: reader.u32()?,
+            },
+            COMMAND_THROTTLE_CONFIGURE => CommandKind::ThrottleConfigure {
+                interval: reader.u32()?,
+                acceleration: reader.u32()?,
+                deceleration: reader.u32()?,
+            },
+            COMMAND_SEND_UNRELIABLE_FRAGMENT => {
+                CommandKind::SendUnreliableFragment(parse_fragment(&mut reader)?)
+            }
+            command => return Err(ProtocolError::UnknownCommand { command, offset }),
+        };
+
+        commands.push(Command {
+            channel_id,
+            reliable_sequence_number,
+            acknowledge: command & COMMAND_FLAG_ACKNOWLEDGE != 0,
+            unsequenced: command & COMMAND_FLAG_UNSEQUENCED != 0,
+            kind,
+        });
+    }
+
+    Ok(commands)
+}
+
+fn parse_connect_parameters(reader: &mut Reader<'_>) -> Result<ConnectParameters, ProtocolError> {
+    Ok(ConnectParameters {
+        outgoing_peer_id: reader.u16()?,
+        incoming_session_id: reader.u8()?,
+        outgoing_session_id: reader.u8()?,
+        mtu: reader.u32()?,
+        window_size: reader.u32()?,
+        channel_count: reader.u32()?,
+        incoming_bandwidth: reader.u32()?,
+        outgoing_bandwidth: reader.u32()?,
+        packet_throttle_interval: reader.u32()?,
+        packet_throttle_acceleration: reader.u32()?,
+        packet_throttle_deceleration: reader.u32()?,
+        connect_id: {
+            let bytes = reader.bytes(4)?;
+            u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        },
+    })
+}
+
+fn parse_fragment(reader: &mut Reader<'_>) -> Result<Fragment, ProtocolError> {
+    let start_sequence_number = reader.u16()?;
+    let len = reader.u16()?;
+
+    Ok(Fragment {
+        start_sequence_number,
+        fragment_count: reader.u32()?,
+        fragment_number: reader.u32()?,
+        total_length: reader.u32()?,
+        fragment_offset: reader.u32()?,
+        data: reader.bytes(usize::from(len))?.to_vec(),
+    })
+}
+
+/// Reads big-endian fields from a datagram, reporting the offset of truncated fields.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    /// Added to offsets in errors, for data that is part of a larger datagram.
+    base: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader {
+            data,
+            offset: 0,
+            base: 0,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.base + self.offset
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.offset..]
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        let bytes = self
+            .rest()
+            .get(..len)
+            .ok_or_else(|| ProtocolError::Truncated(self.offset()))?;
+        self.offset += len;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProtocolError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl CommandKind {
+    /// Returns the name of this command in ENet's `protocol.h`, e.g. `SEND_RELIABLE`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandKind::Acknowledge { .. } => "ACKNOWLEDGE",
+            CommandKind::Connect { .. } => "CONNECT",
+            CommandKind::VerifyConnect(_) => "VERIFY_CONNECT",
+            CommandKind::Disconnect { .. } => "DISCONNECT",
+            CommandKind::Ping => "PING",
+            CommandKind::SendReliable { .. } => "SEND_RELIABLE",
+            CommandKind::SendUnreliable { .. } => "SEND_UNRELIABLE",
+            CommandKind::SendFragment(_) => "SEND_FRAGMENT",
+            CommandKind::SendUnsequenced { .. } => "SEND_UNSEQUENCED",
+            CommandKind::BandwidthLimit { .. } => "BANDWIDTH_LIMIT",
+            CommandKind::ThrottleConfigure { .. } => "THROTTLE_CONFIGURE",
+            CommandKind::SendUnreliableFragment(_) => "SEND_UNRELIABLE_FRAGMENT",
+        }
+    }
+
+    /// Returns the packet contents carried by this command, if any.
+    pub fn data(&self) -> Option<&[u8]> {
+        match self {
+            CommandKind::SendReliable { data }
+            | CommandKind::SendUnreliable { data, .. }
+            | CommandKind::SendUnsequenced { data, .. } => Some(data),
+            CommandKind::SendFragment(fragment) | CommandKind::SendUnreliableFragment(fragment) => {
+                Some(&fragment.data)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Datagram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.header)?;
+        if let Some(checksum) = self.checksum {
+            write!(f, ", checksum {:#010x}", checksum)?;
+        }
+
+        for command in &self.commands {
+            write!(f, "\n  {}", command)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.peer_id {
+            Some(peer_id) => write!(f, "peer {}", peer_id)?,
+            None => write!(f, "no peer")?,
+        }
+        write!(f, ", session {}", self.session_id)?;
+        if let Some(sent_time) = self.sent_time {
+            write!(f, ", sent {}", sent_time)?;
+        }
+        if self.compressed {
+            write!(f, ", compressed")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} channel {} seq {}",
+            self.kind.name(),
+            self.channel_id,
+            self.reliable_sequence_number
+        )?;
+        if self.acknowledge {
+            write!(f, " [ack]")?;
+        }
+        if self.unsequenced {
+            write!(f, " [unsequenced]")?;
+        }
+
+        match &self.kind {
+            CommandKind::Acknowledge {
+                received_reliable_sequence_number,
+                received_sent_time,
+            } => write!(
+                f,
+                ": seq {}, sent {}",
+                received_reliable_sequence_number, received_sent_time
+            ),
+            CommandKind::Connect { parameters, data } => {
+                write!(f, ": {}, data {}", parameters, data)
+            }
+            CommandKind::VerifyConnect(parameters) => write!(f, ": {}", parameters),
+            CommandKind::Disconnect { data } => write!(f, ": data {}", data),
+            CommandKind::Ping => Ok(()),
+            CommandKind::SendReliable { data } => write!(f, ": {}", DisplayData(data)),
+            CommandKind::SendUnreliable {
+                unreliable_sequence_number,
+                data,
+            } => write!(
+                f,
+                ": unreliable seq {}, {}",
+                unreliable_sequence_number,
+                DisplayData(data)
+            ),
+            CommandKind::SendFragment(fragment) | CommandKind::SendUnreliableFragment(fragment) => {
+                write!(f, ": {}", fragment)
+            }
+            CommandKind::SendUnsequenced {
+                unsequenced_group,
+                data,
+            } => write!(f, ": group {}, {}", unsequenced_group, DisplayData(data)),
+            CommandKind::BandwidthLimit {
+                incoming_bandwidth,
+                outgoing_bandwidth,
+            } => write!(
+                f,
+                ": bandwidth {}/{}",
+                incoming_bandwidth, outgoing_bandwidth
+            ),
+            CommandKind::ThrottleConfigure {
+                interval,
+                acceleration,
+                deceleration,
+            } => write!(
+                f,
+                ": throttle {}/{}/{}",
+                interval, acceleration, deceleration
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ConnectParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peer {}, session {}/{}, mtu {}, window {}, channels {}, bandwidth {}/{}, throttle {}/{}/{}, connect id {:#010x}",
+            self.outgoing_peer_id,
+            self.incoming_session_id,
+            self.outgoing_session_id,
+            self.mtu,
+            self.window_size,
+            self.channel_count,
+            self.incoming_bandwidth,
+            self.outgoing_bandwidth,
+            self.packet_throttle_interval,
+            self.packet_throttle_acceleration,
+            self.packet_throttle_deceleration,
+            self.connect_id
+        )
+    }
+}
+
+impl fmt::Display for Fragment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "start seq {}, fragment {}/{}, offset {}/{}, {}",
+            self.start_sequence_number,
+            self.fragment_number,
+            self.fragment_count,
+            self.fragment_offset,
+            self.total_length,
+            DisplayData(&self.data)
+        )
+    }
+}
+
+/// Prints the length of packet contents, and their first bytes in hex.
+struct DisplayData<'a>(&'a [u8]);
+
+impl fmt::Display for DisplayData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes", self.0.len())?;
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, ":")?;
+        for byte in self.0.iter().take(DISPLAYED_DATA_LEN) {
+            write!(f, " {:02x}", byte)?;
+        }
+        if self.0.len() > DISPLAYED_DATA_LEN {
+            write!(f, " ...")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_datagram, CommandKind, Dissector, ProtocolError};
+    use crate::Compressor;
+
+    // CONNECT, VERIFY_CONNECT, ACKNOWLEDGE_PING and SEND_RELIABLE were recorded from a client and a server
+    // on the loopback interface. The client connected with 2 channels and data 7, and sent "hello".
+
+    /// The first datagram of the connection, received by the server.
+    const CONNECT: &[u8] = &[
+        0x8f, 0xff, 0x32, 0x9e, 0x82, 0xff, 0x00, 0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x05,
+        0x78, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x13, 0x88, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0xd6,
+        0xaa, 0xe3, 0x18, 0x00, 0x00, 0x00, 0x07,
+    ];
+
+    /// The server accepts the connection, received by the client.
+    const VERIFY_CONNECT: &[u8] = &[
+        0x80, 0x00, 0x32, 0xa3, 0x83, 0xff, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+        0x78, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x13, 0x88, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0xd6,
+        0xaa, 0xe3, 0x18,
+    ];
+
+    /// The client acknowledges the `VERIFY_CONNECT`, and pings, received by the server.
+    const ACKNOWLEDGE_PING: &[u8] = &[
+        0x80, 0x00, 0x32, 0xad, 0x01, 0xff, 0x00, 0x01, 0x00, 0x01, 0x32, 0xa3, 0x85, 0xff, 0x00,
+        0x02,
+    ];
+
+    /// The client sends "hello" reliably on channel 0, received by the server.
+    const SEND_RELIABLE: &[u8] = &[
+        0x80, 0x00, 0x33, 0x65, 0x86, 0x00, 0x00, 0x01, 0x00, 0x05, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+    ];
+
+    /// The second fragment of an unreliable packet, and an unsequenced packet. Assembled by hand following
+    /// `protocol.h`, as recorded fragments fill a whole datagram.
+    const HAND_ASSEMBLED_UNRELIABLE: &[u8] = &[
+        0x00, 0x00, // peer 0, session 0
+        0x0c, 0x01, 0x00, 0x00, // SEND_UNRELIABLE_FRAGMENT, channel 1, seq 0
+        0x00, 0x03, 0x00, 0x02, // start seq 3, 2 bytes
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, // fragment 1 of 2
+        0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, // total length 6, offset 4
+        0xab, 0xcd, //
+        0x49, 0x01, 0x00, 0x00, // SEND_UNSEQUENCED [unsequenced], channel 1, seq 0
+        0x00, 0x09, 0x00, 0x01, 0xef, // group 9, 1 byte
+    ];
+
+    #[test]
+    fn test_parse_connect() {
+        let datagram = parse_datagram(CONNECT).unwrap();
+
+        assert_eq!(datagram.header.peer_id, None);
+        assert_eq!(datagram.header.sent_time, Some(12958));
+        assert_eq!(datagram.commands.len(), 1);
+
+        let command = &datagram.commands[0];
+        assert!(command.acknowledge);
+        assert_eq!(command.channel_id, 255);
+        match command.kind {
+            CommandKind::Connect {
+                ref parameters,
+                data,
+            } => {
+                assert_eq!(parameters.mtu, 1400);
+                assert_eq!(parameters.channel_count, 2);
+                assert_eq!(
+                    parameters.connect_id,
+                    u32::from_ne_bytes([0xd6, 0xaa, 0xe3, 0x18])
+                );
+                assert_eq!(data, 7);
+            }
+            ref kind => panic!("unexpected command {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_parse_verify_connect() {
+        let datagram = parse_datagram(VERIFY_CONNECT).unwrap();
+
+        assert_eq!(datagram.header.peer_id, Some(0));
+        assert_eq!(datagram.commands.len(), 1);
+        assert_eq!(datagram.commands[0].kind.name(), "VERIFY_CONNECT");
+
+        let datagram = parse_datagram(ACKNOWLEDGE_PING).unwrap();
+        assert_eq!(
+            datagram.commands[0].kind,
+            CommandKind::Acknowledge {
+                received_reliable_sequence_number: 1,
+                received_sent_time: 12963,
+            }
+        );
+        assert_eq!(datagram.commands[1].kind.name(), "PING");
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            parse_datagram(SEND_RELIABLE).unwrap().to_string(),
+            "peer 0, session 0, sent 13157\n  \
+             SEND_RELIABLE channel 0 seq 1 [ack]: 5 bytes: 68 65 6c 6c 6f"
+        );
+        assert_eq!(
+            parse_datagram(HAND_ASSEMBLED_UNRELIABLE).unwrap().to_string(),
+            "peer 0, session 0\n  \
+             SEND_UNRELIABLE_FRAGMENT channel 1 seq 0: start seq 3, fragment 1/2, offset 4/6, 2 bytes: ab cd\n  \
+             SEND_UNSEQUENCED channel 1 seq 0 [unsequenced]: group 9, 1 bytes: ef"
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert_eq!(
+            parse_datagram(&CONNECT[..CONNECT.len() - 1]),
+            Err(ProtocolError::Truncated(48))
+        );
+        assert_eq!(
+            parse_datagram(&[0x00, 0x00, 0x0f, 0xff, 0x00, 0x00]),
+            Err(ProtocolError::UnknownCommand {
+                command: 15,
+                offset: 2
+            })
+        );
+    }
+
+    /// Stores datagrams as they are, with a marker byte in front.
+    struct MarkerCompressor;
+
+    impl Compressor for MarkerCompressor {
+        fn compress(&mut self, _: &[&[u8]], _: usize, _: &mut [u8]) -> Option<usize> {
+            None
+        }
+
+        fn decompress(&mut self, input: &[u8], out: &mut [u8]) -> Option<usize> {
+            let input = input.get(1..).filter(|_| input[0] == 0xcc)?;
+            out[..input.len()].copy_from_slice(input);
+            Some(input.len())
+        }
+    }
+
+    #[test]
+    fn test_compressed_with_checksum() {
+        // SEND_RELIABLE with the compressed flag and a checksum
+        let mut data = vec![0xc0, 0x00, 0x33, 0x65, 0x01, 0x02, 0x03, 0x04, 0xcc];
+        data.extend_from_slice(&SEND_RELIABLE[4..]);
+
+        assert_eq!(
+            Dissector::new().checksum(true).parse(&data),
+            Err(ProtocolError::Compressed)
+        );
+
+        let mut compressor = MarkerCompressor;
+        let datagram = Dissector::new()
+            .checksum(true)
+            .decompressor(&mut compressor)
+            .parse(&data)
+            .unwrap();
+
+        assert!(datagram.header.compressed);
+        assert_eq!(
+            datagram.checksum,
+            Some(u32::from_ne_bytes([0x01, 0x02, 0x03, 0x04]))
+        );
+        assert_eq!(datagram.commands.len(), 1);
+        assert_eq!(datagram.commands[0].kind.data(), Some(&b"hello"[..]));
+    }
+}
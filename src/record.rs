@@ -0,0 +1,626 @@
+//! Recording the traffic of a `Host`, and replaying recorded sessions.
+//!
+//! A `Recorder` is installed on a `Host` through `Host::set_recorder`, and writes
+//!
+//! * every datagram the `Host` receives to a [pcap](https://wiki.wireshark.org/Development/LibpcapFileFormat)
+//!   file, which can be inspected with tools like Wireshark, and
+//! * every `Event` of the `Host` to a compact native format, which can be read back with `read_events`.
+//!
+//! ENet provides no hook for outgoing datagrams, so only received datagrams are captured.
+//!
+//! A `Replayer` reads a pcap file and sends the recorded datagrams to a `Host` again, with their original timing.
+//! Datagrams of each original sender are sent from their own local socket, so the `Host` sees the same peers,
+//! although with different addresses.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
+use enet_sys::ENetHost;
+
+use crate::intercept::socket_address;
+use crate::{Address, Event, PeerId};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 65535;
+/// Packets start with an IPv4 header.
+const PCAP_LINKTYPE_RAW: u32 = 101;
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+const EVENTS_MAGIC: &[u8; 8] = b"ENETREC1";
+
+const EVENT_CONNECT: u8 = 0;
+const EVENT_DISCONNECT: u8 = 1;
+const EVENT_RECEIVE: u8 = 2;
+
+thread_local! {
+    /// The recorder of the `Host` that is currently being serviced on this thread, if any.
+    static ACTIVE_RECORDER: Cell<Option<*mut Recorder>> = Cell::new(None);
+}
+
+/// Records the traffic of a `Host`, see the module documentation.
+///
+/// Only datagrams received by the `Host` are captured, so the pcap file is not a full capture of the traffic:
+/// ENet provides no hook for the datagrams a `Host` sends.
+///
+/// Writing errors can not be reported while the `Host` is serviced. Instead, recording stops at the first error,
+/// which is returned by `Recorder::finish`.
+pub struct Recorder {
+    start: Instant,
+    pcap: Option<Box<dyn Write + Send>>,
+    events: Option<Box<dyn Write + Send>>,
+    /// The address of the recorded `Host`, used as the destination of captured datagrams.
+    local_address: Option<Address>,
+    error: Option<io::Error>,
+}
+
+/// An `Event` read from a recording, see `read_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedEvent {
+    /// A peer connected.
+    Connect {
+        /// The `PeerId` of the new connection.
+        peer_id: PeerId,
+        /// The address of the peer.
+        address: Address,
+        /// The user-specified data of the connection.
+        data: u32,
+    },
+    /// A peer disconnected.
+    Disconnect {
+        /// The `PeerId` of the ended connection.
+        peer_id: PeerId,
+        /// The address of the peer.
+        address: Address,
+        /// The user-specified data of the disconnection.
+        data: u32,
+    },
+    /// A packet was received.
+    Receive {
+        /// The `PeerId` of the sender.
+        peer_id: PeerId,
+        /// The address of the sender.
+        address: Address,
+        /// The channel on which the packet was received.
+        channel_id: u8,
+        /// The contents of the packet.
+        data: Vec<u8>,
+    },
+}
+
+/// A `RecordedEvent`, with the time it occurred at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventRecord {
+    /// The time since the recording started.
+    pub time: Duration,
+    /// The event.
+    pub event: RecordedEvent,
+}
+
+/// A datagram read from a pcap file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedDatagram {
+    /// The time since the first datagram of the recording.
+    pub time: Duration,
+    /// The address of the sender.
+    pub sender: Address,
+    /// The UDP payload, i.e. the ENet datagram.
+    pub data: Vec<u8>,
+}
+
+/// Sends recorded datagrams to a `Host` again, see the module documentation.
+#[derive(Debug)]
+pub struct Replayer {
+    datagrams: Vec<RecordedDatagram>,
+    next: usize,
+    start: Option<Instant>,
+    /// The local sockets standing in for the original senders.
+    sockets: HashMap<Address, UdpSocket>,
+}
+
+impl Recorder {
+    /// Creates a recorder, which does not record anything until a pcap file or an event log is added.
+    pub fn new() -> Recorder {
+        Recorder {
+            start: Instant::now(),
+            pcap: None,
+            events: None,
+            local_address: None,
+            error: None,
+        }
+    }
+
+    /// Writes all received datagrams to `writer`, in the pcap format.
+    pub fn pcap<W: Write + Send + 'static>(mut self, mut writer: W) -> io::Result<Recorder> {
+        writer.write_u32::<LittleEndian>(PCAP_MAGIC)?;
+        writer.write_u16::<LittleEndian>(2)?;
+        writer.write_u16::<LittleEndian>(4)?;
+        // timezone offset and timestamp accuracy
+        writer.write_i32::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(PCAP_SNAPLEN)?;
+        writer.write_u32::<LittleEndian>(PCAP_LINKTYPE_RAW)?;
+
+        self.pcap = Some(Box::new(writer));
+        Ok(self)
+    }
+
+    /// Writes all events to `writer`, in the native format read by `read_events`.
+    pub fn events<W: Write + Send + 'static>(mut self, mut writer: W) -> io::Result<Recorder> {
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        writer.write_all(EVENTS_MAGIC)?;
+        writer.write_u64::<NetworkEndian>(start.as_micros() as u64)?;
+
+        self.events = Some(Box::new(writer));
+        Ok(self)
+    }
+
+    /// Flushes all output, and returns the first error that occurred while recording, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        if let Some(pcap) = self.pcap.as_mut() {
+            pcap.flush()?;
+        }
+        if let Some(events) = self.events.as_mut() {
+            events.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs `write` on `output`, and stops recording to it if an error occurs.
+    fn write_to<F>(
+        output: &mut Option<Box<dyn Write + Send>>,
+        error: &mut Option<io::Error>,
+        write: F,
+    ) where
+        F: FnOnce(&mut dyn Write) -> io::Result<()>,
+    {
+        if let Some(writer) = output.as_mut() {
+            if let Err(err) = write(writer) {
+                *output = None;
+                error.get_or_insert(err);
+            }
+        }
+    }
+
+    unsafe fn record_datagram(&mut self, host: *mut ENetHost) {
+        if self.pcap.is_none() {
+            return;
+        }
+
+        if self.local_address.is_none() {
+            self.local_address = socket_address(host);
+        }
+
+        let sender = Address::from_enet_address(&(*host).receivedAddress);
+        let data = std::slice::from_raw_parts((*host).receivedData, (*host).receivedDataLength);
+        let receiver = self
+            .local_address
+            .clone()
+            .unwrap_or_else(|| Address::new(Ipv4Addr::UNSPECIFIED, 0));
+        let packet = udp_packet(&sender, &receiver, data);
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Recorder::write_to(&mut self.pcap, &mut self.error, |w| {
+            w.write_u32::<LittleEndian>(time.as_secs() as u32)?;
+            w.write_u32::<LittleEndian>(time.subsec_micros())?;
+            w.write_u32::<LittleEndian>(packet.len() as u32)?;
+            w.write_u32::<LittleEndian>(packet.len() as u32)?;
+            w.write_all(&packet)
+        });
+    }
+
+    pub(crate) fn record_event<T>(&mut self, event: &Event<'_, T>) {
+        if self.events.is_none() {
+            return;
+        }
+
+        let time = self.start.elapsed();
+        let (kind, peer_id, address) = match event {
+            Event::Connect(peer, peer_id, _) => (EVENT_CONNECT, *peer_id, peer.address()),
+            Event::Disconnect(peer, peer_id, _) => (EVENT_DISCONNECT, *peer_id, peer.address()),
+            Event::Receive {
+                sender, sender_id, ..
+            } => (EVENT_RECEIVE, *sender_id, sender.address()),
+        };
+
+        Recorder::write_to(&mut self.events, &mut self.error, |w| {
+            w.write_u64::<NetworkEndian>(time.as_micros() as u64)?;
+            w.write_u8(kind)?;
+            w.write_u32::<NetworkEndian>(peer_id.index() as u32)?;
+            w.write_u32::<NetworkEndian>(peer_id.connect_id())?;
+            w.write_all(&address.ip().octets())?;
+            w.write_u16::<NetworkEndian>(address.port())?;
+
+            match event {
+                Event::Connect(_, _, data) | Event::Disconnect(_, _, data) => {
+                    w.write_u32::<NetworkEndian>(*data)
+                }
+                Event::Receive {
+                    channel_id, packet, ..
+                } => {
+                    w.write_u8(*channel_id)?;
+                    w.write_u32::<NetworkEndian>(packet.data().len() as u32)?;
+                    w.write_all(packet.data())
+                }
+            }
+        });
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Recorder {
+        Recorder::new()
+    }
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("pcap", &self.pcap.is_some())
+            .field("events", &self.events.is_some())
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+/// Makes a recorder available to ENet on this thread, until the returned guard is dropped.
+pub(crate) struct ActiveRecorderGuard {
+    previous: Option<*mut Recorder>,
+}
+
+impl ActiveRecorderGuard {
+    pub(crate) fn new(recorder: Option<&mut Recorder>) -> ActiveRecorderGuard {
+        let previous = ACTIVE_RECORDER.with(|active| active.replace(recorder.map(|r| r as *mut _)));
+
+        ActiveRecorderGuard { previous }
+    }
+}
+
+impl Drop for ActiveRecorderGuard {
+    fn drop(&mut self) {
+        ACTIVE_RECORDER.with(|active| active.set(self.previous));
+    }
+}
+
+/// Records the datagram `host` just received with the active recorder, if any.
+pub(crate) unsafe fn record_datagram(host: *mut ENetHost) {
+    if let Some(recorder) = ACTIVE_RECORDER.with(Cell::get) {
+        (*recorder).record_datagram(host);
+    }
+}
+
+/// Builds an IPv4/UDP packet carrying `data` from `sender` to `receiver`. The UDP checksum is left empty.
+fn udp_packet(sender: &Address, receiver: &Address, data: &[u8]) -> Vec<u8> {
+    let total_len = IPV4_HEADER_LEN + UDP_HEADER_LEN + data.len();
+    let mut packet = Vec::with_capacity(total_len);
+
+    // writing to a `Vec` can not fail
+    packet.push(0x45); // version 4, header length 5 * 4 bytes
+    packet.push(0);
+    packet.write_u16::<NetworkEndian>(total_len as u16).unwrap();
+    packet.extend_from_slice(&[0, 0, 0x40, 0]); // identification, don't fragment
+    packet.push(64); // ttl
+    packet.push(IP_PROTOCOL_UDP);
+    packet.extend_from_slice(&[0, 0]); // header checksum, filled in below
+    packet.extend_from_slice(&sender.ip().octets());
+    packet.extend_from_slice(&receiver.ip().octets());
+
+    let checksum = ipv4_checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    packet.write_u16::<NetworkEndian>(sender.port()).unwrap();
+    packet.write_u16::<NetworkEndian>(receiver.port()).unwrap();
+    packet
+        .write_u16::<NetworkEndian>((UDP_HEADER_LEN + data.len()) as u16)
+        .unwrap();
+    packet.write_u16::<NetworkEndian>(0).unwrap();
+    packet.extend_from_slice(data);
+
+    packet
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|c| u32::from(u16::from_be_bytes([c[0], c[1]])))
+        .sum();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// Reads the events of a recording written by a `Recorder`.
+pub fn read_events<R: Read>(mut reader: R) -> io::Result<Vec<EventRecord>> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != EVENTS_MAGIC {
+        return Err(invalid_data("not an event recording"));
+    }
+    let _start = reader.read_u64::<NetworkEndian>()?;
+
+    let mut records = Vec::new();
+    loop {
+        let time = match reader.read_u64::<NetworkEndian>() {
+            Ok(time) => Duration::from_micros(time),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+
+        let kind = reader.read_u8()?;
+        let index = reader.read_u32::<NetworkEndian>()? as usize;
+        let connect_id = reader.read_u32::<NetworkEndian>()?;
+        let peer_id = PeerId::new(index, connect_id);
+        let mut ip = [0; 4];
+        reader.read_exact(&mut ip)?;
+        let address = Address::new(Ipv4Addr::from(ip), reader.read_u16::<NetworkEndian>()?);
+
+        let event = match kind {
+            EVENT_CONNECT => RecordedEvent::Connect {
+                peer_id,
+                address,
+                data: reader.read_u32::<NetworkEndian>()?,
+            },
+            EVENT_DISCONNECT => RecordedEvent::Disconnect {
+                peer_id,
+                address,
+                data: reader.read_u32::<NetworkEndian>()?,
+            },
+            EVENT_RECEIVE => {
+                let channel_id = reader.read_u8()?;
+                let len = reader.read_u32::<NetworkEndian>()?;
+                let data = read_bytes(&mut reader, len)?;
+
+                RecordedEvent::Receive {
+                    peer_id,
+                    address,
+                    channel_id,
+                    data,
+                }
+            }
+            _ => return Err(invalid_data("unknown event kind")),
+        };
+
+        records.push(EventRecord { time, event });
+    }
+
+    Ok(records)
+}
+
+/// Reads the UDP datagrams of a pcap file, as written by a `Recorder`.
+///
+/// Only IPv4 captures without link layer headers (`LINKTYPE_RAW`) are supported. Packets that are not
+/// IPv4/UDP are skipped.
+pub fn read_pcap<R: Read>(mut reader: R) -> io::Result<Vec<RecordedDatagram>> {
+    let magic = reader.read_u32::<LittleEndian>()?;
+    let big_endian = match magic {
+        PCAP_MAGIC => false,
+        m if m.swap_bytes() == PCAP_MAGIC => true,
+        _ => return Err(invalid_data("not a pcap file")),
+    };
+
+    let read_u32 = |reader: &mut R| -> io::Result<u32> {
+        if big_endian {
+            reader.read_u32::<NetworkEndian>()
+        } else {
+            reader.read_u32::<LittleEndian>()
+        }
+    };
+
+    // version, timezone offset, timestamp accuracy
+    let mut rest = [0; 12];
+    reader.read_exact(&mut rest)?;
+    let snaplen = read_u32(&mut reader)?;
+    if read_u32(&mut reader)? != PCAP_LINKTYPE_RAW {
+        return Err(invalid_data("unsupported pcap link type"));
+    }
+
+    let mut first = None;
+    let mut datagrams = Vec::new();
+    loop {
+        let secs = match read_u32(&mut reader) {
+            Ok(secs) => secs,
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        let micros = read_u32(&mut reader)?;
+        let captured_len = read_u32(&mut reader)?;
+        let _original_len = read_u32(&mut reader)?;
+        if captured_len > snaplen {
+            return Err(invalid_data("captured packet exceeds the snapshot length"));
+        }
+        let packet = read_bytes(&mut reader, captured_len)?;
+
+        let timestamp =
+            Duration::from_secs(u64::from(secs)) + Duration::from_micros(u64::from(micros));
+        let first = *first.get_or_insert(timestamp);
+
+        if let Some((sender, data)) = parse_udp_packet(&packet) {
+            datagrams.push(RecordedDatagram {
+                time: timestamp.checked_sub(first).unwrap_or_default(),
+                sender,
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    Ok(datagrams)
+}
+
+/// Reads `len` bytes, without trusting `len` for the allocation.
+fn read_bytes<R: Read>(reader: &mut R, len: u32) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut data)?;
+    if data.len() != len as usize {
+        return Err(invalid_data("unexpected end of data"));
+    }
+
+    Ok(data)
+}
+
+/// Returns the sender and payload of an IPv4/UDP packet.
+fn parse_udp_packet(packet: &[u8]) -> Option<(Address, &[u8])> {
+    if packet.len() < IPV4_HEADER_LEN || packet[0] >> 4 != 4 || packet[9] != IP_PROTOCOL_UDP {
+        return None;
+    }
+
+    let header_len = usize::from(packet[0] & 0x0f) * 4;
+    let udp = packet.get(header_len..)?;
+    if udp.len() < UDP_HEADER_LEN {
+        return None;
+    }
+
+    let ip = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+    let port = u16::from_be_bytes([udp[0], udp[1]]);
+    let udp_len = usize::from(u16::from_be_bytes([udp[4], udp[5]]));
+    let payload = udp.get(UDP_HEADER_LEN..std::cmp::max(udp_len, UDP_HEADER_LEN))?;
+
+    Some((Address::new(ip, port), payload))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Replayer {
+    /// Creates a replayer for `datagrams`, e.g. as read by `read_pcap`.
+    pub fn new(datagrams: Vec<RecordedDatagram>) -> Replayer {
+        Replayer {
+            datagrams,
+            next: 0,
+            start: None,
+            sockets: HashMap::new(),
+        }
+    }
+
+    /// Sends all datagrams to `target` whose time has come, and returns how many were sent.
+    ///
+    /// The replay starts with the first call, so this should be called regularly, e.g. before each service
+    /// of the `Host` listening on `target`.
+    pub fn replay_due(&mut self, target: &Address) -> io::Result<usize> {
+        let elapsed = self.start.get_or_insert_with(Instant::now).elapsed();
+        let mut sent = 0;
+
+        while let Some(datagram) = self.datagrams.get(self.next) {
+            if datagram.time > elapsed {
+                break;
+            }
+
+            let socket = match self.sockets.get(&datagram.sender) {
+                Some(socket) => socket,
+                None => {
+                    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+                    self.sockets
+                        .entry(datagram.sender.clone())
+                        .or_insert(socket)
+                }
+            };
+            socket.send_to(&datagram.data, (*target.ip(), target.port()))?;
+
+            self.next += 1;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    /// Returns whether all datagrams have been sent.
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.datagrams.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_udp_packet, read_events, read_pcap, udp_packet, EVENTS_MAGIC, EVENT_RECEIVE,
+    };
+    use crate::Address;
+
+    use std::io::ErrorKind;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_udp_packet() {
+        let sender = Address::new(Ipv4Addr::new(10, 0, 0, 1), 1234);
+        let receiver = Address::new(Ipv4Addr::LOCALHOST, 5678);
+        let packet = udp_packet(&sender, &receiver, b"datagram");
+
+        assert_eq!(packet.len(), 20 + 8 + 8);
+        assert_eq!(super::ipv4_checksum(&packet[..20]), 0);
+        assert_eq!(parse_udp_packet(&packet), Some((sender, &b"datagram"[..])));
+    }
+
+    #[test]
+    fn test_read_pcap() {
+        let sender = Address::new(Ipv4Addr::new(10, 0, 0, 1), 1234);
+        let receiver = Address::new(Ipv4Addr::LOCALHOST, 5678);
+        let packet = udp_packet(&sender, &receiver, b"datagram");
+
+        // big-endian global header, as written by other tools
+        let mut pcap = vec![
+            0xa1, 0xb2, 0xc3, 0xd4, 0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0,
+            101,
+        ];
+        for &(secs, micros) in &[(10u32, 500_000u32), (11, 0)] {
+            pcap.extend_from_slice(&secs.to_be_bytes());
+            pcap.extend_from_slice(&micros.to_be_bytes());
+            pcap.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            pcap.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            pcap.extend_from_slice(&packet);
+        }
+
+        let datagrams = read_pcap(&pcap[..]).unwrap();
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0].time, Duration::from_secs(0));
+        assert_eq!(datagrams[1].time, Duration::from_millis(500));
+        assert_eq!(datagrams[1].sender, sender);
+        assert_eq!(datagrams[1].data, b"datagram");
+    }
+
+    #[test]
+    fn test_corrupt_lengths() {
+        // a received packet claiming 4 GiB, of which only 3 bytes follow
+        let mut events = EVENTS_MAGIC.to_vec();
+        events.extend_from_slice(&[0; 16]);
+        events.push(EVENT_RECEIVE);
+        events.extend_from_slice(&[0; 4 + 4 + 4 + 2 + 1]);
+        events.extend_from_slice(&u32::max_value().to_be_bytes());
+        events.extend_from_slice(b"abc");
+        let err = read_events(&events[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let header = [
+            0xa1, 0xb2, 0xc3, 0xd4, 0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0,
+            101,
+        ];
+        for &captured_len in &[0x1_0000u32, 100] {
+            let mut pcap = header.to_vec();
+            pcap.extend_from_slice(&[0; 8]);
+            pcap.extend_from_slice(&captured_len.to_be_bytes());
+            pcap.extend_from_slice(&captured_len.to_be_bytes());
+            pcap.extend_from_slice(&[0; 10]);
+            let err = read_pcap(&pcap[..]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! Requests and responses on top of a reliable ENet channel.
+//!
+//! An `Rpc` multiplexes requests and responses over a single, dedicated channel. Each request carries a
+//! correlation id, which is used to match the response to the request. Servers register handlers for named
+//! methods, which are called whenever a request for that method arrives.
+//!
+//! `Rpc` does not service the `Host` itself. Instead, every event returned by `Host::service` has to be
+//! passed to `Rpc::handle_event`, and finished requests are retrieved through `Rpc::next_response`:
+//!
+//! ```no_run
+//! # fn run(host: &mut enet::Host<()>, server: enet::PeerId) -> Result<(), enet::Error> {
+//! use enet::rpc::Rpc;
+//!
+//! let mut rpc = Rpc::new(0);
+//! rpc.register("echo", |_peer_id, payload| Ok(payload.to_vec()));
+//!
+//! rpc.request(&mut host.peer(server).unwrap(), "echo", b"hello")?;
+//!
+//! loop {
+//!     if let Some(mut event) = host.service(10)? {
+//!         if !rpc.handle_event(&mut event) {
+//!             // not an rpc packet, handle the event as usual
+//!         }
+//!     }
+//!
+//!     while let Some(response) = rpc.next_response() {
+//!         println!("{:?}", response.result);
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::time::{Clock, SystemClock};
+use crate::{Error, Event, Packet, PacketMode, Peer, PeerId};
+
+/// The default for `Rpc::set_timeout`.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const KIND_REQUEST: u8 = 0;
+const KIND_RESPONSE: u8 = 1;
+
+const STATUS_OK: u8 = 0;
+const STATUS_UNKNOWN_METHOD: u8 = 1;
+const STATUS_HANDLER_ERROR: u8 = 2;
+
+type HandlerFn = dyn FnMut(PeerId, &[u8]) -> Result<Vec<u8>, String> + Send;
+
+/// The correlation id of a request, which identifies it until its response arrives.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RequestId(u32);
+
+/// The reason a request did not succeed.
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// No response arrived before the timeout elapsed.
+    #[fail(display = "the request timed out")]
+    TimedOut,
+    /// The peer disconnected before it responded.
+    #[fail(display = "the peer disconnected")]
+    Disconnected,
+    /// The peer has no handler for the requested method.
+    #[fail(display = "unknown method '{}'", _0)]
+    UnknownMethod(String),
+    /// The handler on the peer returned an error.
+    #[fail(display = "the request failed: {}", _0)]
+    Failed(String),
+}
+
+/// The outcome of a request, returned by `Rpc::next_response`.
+#[derive(Debug)]
+pub struct RpcResponse {
+    /// The id of the request, as returned by `Rpc::request`.
+    pub id: RequestId,
+    /// The peer the request was sent to.
+    pub peer_id: PeerId,
+    /// The payload returned by the handler on the peer, or the reason the request failed.
+    pub result: Result<Vec<u8>, RpcError>,
+}
+
+/// A request that is waiting for its response.
+#[derive(Debug)]
+struct PendingRequest {
+    peer_id: PeerId,
+    deadline: Instant,
+}
+
+/// Sends requests and answers them, see the module documentation.
+pub struct Rpc {
+    channel_id: u8,
+    timeout: Duration,
+    next_id: u32,
+    pending: HashMap<u32, PendingRequest>,
+    responses: VecDeque<RpcResponse>,
+    handlers: HashMap<String, Box<HandlerFn>>,
+    clock: Box<dyn Clock + Send>,
+}
+
+/// A message on the rpc channel.
+#[derive(Debug, PartialEq)]
+enum Message<'a> {
+    Request {
+        id: u32,
+        method: &'a str,
+        payload: &'a [u8],
+    },
+    Response {
+        id: u32,
+        status: u8,
+        payload: &'a [u8],
+    },
+}
+
+impl<'a> Message<'a> {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        // writing to a `Vec` can not fail
+        match *self {
+            Message::Request {
+                id,
+                method,
+                payload,
+            } => {
+                data.push(KIND_REQUEST);
+                data.write_u32::<NetworkEndian>(id).unwrap();
+                data.write_u16::<NetworkEndian>(method.len() as u16)
+                    .unwrap();
+                data.extend_from_slice(method.as_bytes());
+                data.extend_from_slice(payload);
+            }
+            Message::Response {
+                id,
+                status,
+                payload,
+            } => {
+                data.push(KIND_RESPONSE);
+                data.write_u32::<NetworkEndian>(id).unwrap();
+                data.push(status);
+                data.extend_from_slice(payload);
+            }
+        }
+
+        data
+    }
+
+    /// Returns `None` if `data` is not a valid message.
+    fn decode(mut data: &'a [u8]) -> Option<Message<'a>> {
+        let kind = data.read_u8().ok()?;
+        let id = data.read_u32::<NetworkEndian>().ok()?;
+
+        match kind {
+            KIND_REQUEST => {
+                let method_len = data.read_u16::<NetworkEndian>().ok()? as usize;
+                if data.len() < method_len {
+                    return None;
+                }
+
+                let (method, payload) = data.split_at(method_len);
+
+                Some(Message::Request {
+                    id,
+                    method: std::str::from_utf8(method).ok()?,
+                    payload,
+                })
+            }
+            KIND_RESPONSE => {
+                let status = data.read_u8().ok()?;
+
+                Some(Message::Response {
+                    id,
+                    status,
+                    payload: data,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Rpc {
+    /// Creates an `Rpc` that sends and receives its messages on `channel_id`.
+    ///
+    /// The channel should not be used for anything else, and must be the same on both ends of a connection.
+    pub fn new(channel_id: u8) -> Rpc {
+        Rpc {
+            channel_id,
+            timeout: DEFAULT_TIMEOUT,
+            next_id: 0,
+            pending: HashMap::new(),
+            responses: VecDeque::new(),
+            handlers: HashMap::new(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Returns the channel this `Rpc` uses.
+    pub fn channel_id(&self) -> u8 {
+        self.channel_id
+    }
+
+    /// Sets how long requests sent from now on wait for their response (5 seconds by default).
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the clock used for the timeouts of requests (`time::SystemClock` by default).
+    ///
+    /// Using a `time::ManualClock` or `time::EnetClock` allows tests to time out requests without sleeping.
+    pub fn set_clock<C: Clock + Send + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Registers `handler` for requests for `method`, replacing any previous handler.
+    ///
+    /// The handler receives the id of the requesting peer and the request payload, and returns the response
+    /// payload, or an error message which is reported to the requesting side as `RpcError::Failed`.
+    pub fn register<F>(&mut self, method: &str, handler: F)
+    where
+        F: FnMut(PeerId, &[u8]) -> Result<Vec<u8>, String> + Send + 'static,
+    {
+        self.handlers.insert(method.to_owned(), Box::new(handler));
+    }
+
+    /// Removes the handler for `method`, if any.
+    pub fn unregister(&mut self, method: &str) {
+        self.handlers.remove(method);
+    }
+
+    /// Sends a request for `method` to `peer`, and returns its id.
+    ///
+    /// The outcome of the request is returned by `Rpc::next_response`, once the response arrived,
+    /// the timeout elapsed, or the peer disconnected.
+    pub fn request<T>(
+        &mut self,
+        peer: &mut Peer<'_, T>,
+        method: &str,
+        payload: &[u8],
+    ) -> Result<RequestId, Error> {
+        assert!(
+            method.len() <= u16::max_value() as usize,
+            "method name is too long"
+        );
+
+        let id = self.next_id;
+        let message = Message::Request {
+            id,
+            method,
+            payload,
+        };
+        self.send(peer, &message)?;
+
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.insert(
+            id,
+            PendingRequest {
+                peer_id: peer.id(),
+                deadline: self.clock.now() + self.timeout,
+            },
+        );
+
+        Ok(RequestId(id))
+    }
+
+    /// Returns the number of requests that are still waiting for their response.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Processes `event`, which has to be passed to the `Rpc` for every event returned by the `Host`.
+    ///
+    /// Requests are answered by the registered handlers right away, and responses are queued for
+    /// `Rpc::next_response`. When a peer disconnects, all requests sent to it fail.
+    /// Returns whether the event was a packet on the rpc channel, which needs no further handling.
+    pub fn handle_event<T>(&mut self, event: &mut Event<'_, T>) -> bool {
+        match event {
+            Event::Disconnect(_, peer_id, _) => {
+                self.cancel_requests(*peer_id);
+                false
+            }
+            Event::Receive {
+                sender,
+                sender_id,
+                channel_id,
+                packet,
+            } if *channel_id == self.channel_id => {
+                // malformed messages are ignored
+                match Message::decode(packet.data()) {
+                    Some(Message::Request {
+                        id,
+                        method,
+                        payload,
+                    }) => {
+                        let (status, payload) = self.dispatch(*sender_id, method, payload);
+                        let response = Message::Response {
+                            id,
+                            status,
+                            payload: &payload,
+                        };
+
+                        // if the response can not be sent, the request times out on the other side
+                        let _ = self.send(sender, &response);
+                    }
+                    Some(Message::Response {
+                        id,
+                        status,
+                        payload,
+                    }) => self.complete(*sender_id, id, status, payload),
+                    None => (),
+                }
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the outcome of the next finished request, if any.
+    pub fn next_response(&mut self) -> Option<RpcResponse> {
+        let now = self.clock.now();
+        self.expire_requests(now);
+
+        self.responses.pop_front()
+    }
+
+    fn send<T>(&self, peer: &mut Peer<'_, T>, message: &Message<'_>) -> Result<(), Error> {
+        let packet = Packet::from_vec(message.encode(), PacketMode::ReliableSequenced)?;
+
+        peer.send_packet(packet, self.channel_id)
+    }
+
+    fn dispatch(&mut self, peer_id: PeerId, method: &str, payload: &[u8]) -> (u8, Vec<u8>) {
+        let handler = match self.handlers.get_mut(method) {
+            Some(handler) => handler,
+            None => return (STATUS_UNKNOWN_METHOD, method.as_bytes().to_vec()),
+        };
+
+        match handler(peer_id, payload) {
+            Ok(response) => (STATUS_OK, response),
+            Err(message) => (STATUS_HANDLER_ERROR, message.into_bytes()),
+        }
+    }
+
+    fn complete(&mut self, peer_id: PeerId, id: u32, status: u8, payload: &[u8]) {
+        // ignore responses to unknown requests, e.g. if they already timed out
+        match self.pending.get(&id) {
+            Some(request) if request.peer_id == peer_id => (),
+            _ => return,
+        }
+        self.pending.remove(&id);
+
+        let text = || String::from_utf8_lossy(payload).into_owned();
+        let result = match status {
+            STATUS_OK => Ok(payload.to_vec()),
+            STATUS_UNKNOWN_METHOD => Err(RpcError::UnknownMethod(text())),
+            _ => Err(RpcError::Failed(text())),
+        };
+
+        self.responses.push_back(RpcResponse {
+            id: RequestId(id),
+            peer_id,
+            result,
+        });
+    }
+
+    fn cancel_requests(&mut self, peer_id: PeerId) {
+        self.fail_requests(|request| request.peer_id == peer_id, RpcError::Disconnected);
+    }
+
+    fn expire_requests(&mut self, now: Instant) {
+        self.fail_requests(|request| request.deadline <= now, RpcError::TimedOut);
+    }
+
+    fn fail_requests<F>(&mut self, mut filter: F, error: RpcError)
+    where
+        F: FnMut(&PendingRequest) -> bool,
+    {
+        let mut ids: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, request)| filter(request))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+
+        for id in ids {
+            let request = self.pending.remove(&id).unwrap();
+
+            self.responses.push_back(RpcResponse {
+                id: RequestId(id),
+                peer_id: request.peer_id,
+                result: Err(error.clone()),
+            });
+        }
+    }
+}
+
+impl std::fmt::Debug for Rpc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rpc")
+            .field("channel_id", &self.channel_id)
+            .field("timeout", &self.timeout)
+            .field("pending", &self.pending)
+            .field("handlers", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, KIND_RESPONSE};
+
+    #[test]
+    fn test_message_roundtrip() {
+        let request = Message::Request {
+            id: 7,
+            method: "inventory",
+            payload: b"items",
+        };
+        assert_eq!(Message::decode(&request.encode()), Some(request));
+
+        let response = Message::Response {
+            id: 7,
+            status: 0,
+            payload: b"",
+        };
+        assert_eq!(Message::decode(&response.encode()), Some(response));
+    }
+
+    #[test]
+    fn test_malformed_messages() {
+        assert_eq!(Message::decode(b""), None);
+        assert_eq!(Message::decode(&[KIND_RESPONSE, 0, 0]), None);
+        assert_eq!(Message::decode(&[9, 0, 0, 0, 0, 0]), None);
+        // method length exceeds the message
+        assert_eq!(Message::decode(&[0, 0, 0, 0, 1, 0, 5, b'a']), None);
+    }
+}
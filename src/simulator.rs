@@ -0,0 +1,439 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::os::raw::c_void;
+use std::time::{Duration, Instant};
+
+use enet_sys::{enet_socket_send, ENetBuffer, ENetHost};
+
+use crate::intercept::socket_address;
+use crate::{Address, InterceptAction};
+
+/// The content of the datagrams a `Host` sends to itself, to deliver delayed datagrams.
+pub(crate) const WAKE_UP: &[u8] = b"enet-rs network simulator wake-up";
+
+thread_local! {
+    /// The simulator of the `Host` that is currently being serviced on this thread, if any.
+    static ACTIVE_SIMULATOR: Cell<Option<*mut NetworkSimulator>> = Cell::new(None);
+}
+
+/// The conditions a `NetworkSimulator` applies to incoming datagrams.
+///
+/// Probabilities range from 0.0 (never) to 1.0 (always). The default applies no conditions at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// The delay added to every datagram.
+    pub latency: Duration,
+    /// The maximum random delay added to `latency`.
+    pub jitter: Duration,
+    /// The probability of dropping a datagram.
+    pub loss: f64,
+    /// The probability of delivering a datagram twice.
+    pub duplication: f64,
+    /// The probability of delivering a datagram without any delay, ahead of earlier delayed datagrams.
+    pub reordering: f64,
+    /// The bandwidth of the link in bytes per second, or `None` if it is unlimited.
+    ///
+    /// Datagrams are queued until the link can deliver them.
+    pub bandwidth: Option<u32>,
+}
+
+/// Counters of the datagrams handled by a `NetworkSimulator`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SimulatorStats {
+    /// Number of datagrams received from the network.
+    pub received: u64,
+    /// Number of datagrams dropped.
+    pub dropped: u64,
+    /// Number of datagrams delivered twice.
+    pub duplicated: u64,
+    /// Number of datagrams delivered with a delay.
+    pub delayed: u64,
+}
+
+/// Simulates bad network conditions for the datagrams received by a `Host`.
+///
+/// Installed through `Host::set_network_simulator`. ENet provides no hook for outgoing datagrams,
+/// so a simulator only affects the direction towards its `Host`. To affect both directions of a connection,
+/// install simulators on both ends. All random decisions are drawn from a generator seeded by the user,
+/// so a simulation can be reproduced given the same sequence of datagrams.
+///
+/// Delayed datagrams are delivered by `Host::service` once they are due. For this, the `Host` sends
+/// small wake-up datagrams to its own socket, which are hidden from ENet, intercept hooks and `TrafficStats`.
+#[derive(Debug)]
+pub struct NetworkSimulator {
+    conditions: NetworkConditions,
+    peer_conditions: HashMap<Address, NetworkConditions>,
+    rng: XorShift,
+    /// Datagrams waiting to be delivered, ordered by their release time.
+    queue: Vec<DelayedDatagram>,
+    /// The time at which the link to each sender is free again, for bandwidth limits.
+    links: HashMap<Address, Instant>,
+    /// The number of wake-up datagrams sent, but not yet received.
+    pending_wake_ups: usize,
+    /// The datagram that is currently handed to ENet. It must stay alive until ENet has processed it.
+    current: Vec<u8>,
+    stats: SimulatorStats,
+}
+
+#[derive(Debug)]
+struct DelayedDatagram {
+    release: Instant,
+    sender: Address,
+    data: Vec<u8>,
+}
+
+impl NetworkSimulator {
+    /// Creates a simulator that applies `conditions` to all datagrams, using `seed` for all random decisions.
+    pub fn new(seed: u64, conditions: NetworkConditions) -> NetworkSimulator {
+        NetworkSimulator {
+            conditions,
+            peer_conditions: HashMap::new(),
+            rng: XorShift::new(seed),
+            queue: Vec::new(),
+            links: HashMap::new(),
+            pending_wake_ups: 0,
+            current: Vec::new(),
+            stats: SimulatorStats::default(),
+        }
+    }
+
+    /// Returns the conditions applied to datagrams from senders without their own conditions.
+    pub fn conditions(&self) -> &NetworkConditions {
+        &self.conditions
+    }
+
+    /// Sets the conditions applied to datagrams from senders without their own conditions.
+    pub fn set_conditions(&mut self, conditions: NetworkConditions) {
+        self.conditions = conditions;
+    }
+
+    /// Sets the conditions applied to datagrams from `sender`, e.g. the address of a single peer.
+    pub fn set_peer_conditions(&mut self, sender: Address, conditions: NetworkConditions) {
+        self.peer_conditions.insert(sender, conditions);
+    }
+
+    /// Removes the conditions of `sender`, so the default conditions apply again.
+    pub fn clear_peer_conditions(&mut self, sender: &Address) {
+        self.peer_conditions.remove(sender);
+    }
+
+    /// Returns the number of datagrams that are waiting to be delivered.
+    pub fn queued_datagrams(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns the counters of the datagrams handled by this simulator.
+    pub fn stats(&self) -> SimulatorStats {
+        self.stats
+    }
+
+    /// Decides what happens to a datagram that was received from `sender` at `now`.
+    fn on_datagram(&mut self, now: Instant, sender: &Address, data: &[u8]) -> InterceptAction {
+        self.stats.received += 1;
+
+        let conditions = self
+            .peer_conditions
+            .get(sender)
+            .unwrap_or(&self.conditions)
+            .clone();
+
+        if self.rng.chance(conditions.loss) {
+            self.stats.dropped += 1;
+            return InterceptAction::Consume;
+        }
+
+        let mut release = now;
+
+        if let Some(bandwidth) = conditions.bandwidth {
+            let link = self.links.entry(sender.clone()).or_insert(now);
+            let start = std::cmp::max(*link, now);
+            *link =
+                start + Duration::from_secs_f64(data.len() as f64 / f64::from(bandwidth.max(1)));
+            release = *link;
+        }
+
+        if !self.rng.chance(conditions.reordering) {
+            release += conditions.latency + conditions.jitter.mul_f64(self.rng.next_f64());
+        }
+
+        let copies = if self.rng.chance(conditions.duplication) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+
+        if copies == 1 && release <= now {
+            return InterceptAction::PassThrough;
+        }
+
+        self.stats.delayed += 1;
+        for _ in 0..copies {
+            self.enqueue(DelayedDatagram {
+                release,
+                sender: sender.clone(),
+                data: data.to_vec(),
+            });
+        }
+
+        InterceptAction::Consume
+    }
+
+    fn enqueue(&mut self, datagram: DelayedDatagram) {
+        // datagrams with the same release time keep their order
+        let index = self
+            .queue
+            .iter()
+            .position(|d| d.release > datagram.release)
+            .unwrap_or(self.queue.len());
+
+        self.queue.insert(index, datagram);
+    }
+
+    /// Returns the number of datagrams that are due at `now`.
+    fn due_count(&self, now: Instant) -> usize {
+        self.queue.iter().take_while(|d| d.release <= now).count()
+    }
+
+    /// Shortens `timeout_ms`, so the host is serviced again when the next delayed datagram is due.
+    pub(crate) fn limit_timeout(&self, timeout_ms: u32) -> u32 {
+        match self.queue.first() {
+            Some(datagram) => {
+                let remaining = datagram.release.saturating_duration_since(Instant::now());
+                // round up, so the datagram is due once the timeout elapsed
+                let remaining_ms = remaining.as_micros().saturating_add(999) / 1000;
+
+                std::cmp::min(u128::from(timeout_ms), remaining_ms) as u32
+            }
+            None => timeout_ms,
+        }
+    }
+
+    /// Sends a wake-up datagram to `host` for every due datagram, so they are delivered during the next service.
+    pub(crate) fn wake_up(&mut self, host: *mut ENetHost) {
+        let due = self.due_count(Instant::now());
+        if due <= self.pending_wake_ups {
+            return;
+        }
+
+        let address = match unsafe { wake_up_address(host) } {
+            Some(address) => address.to_enet_address(),
+            None => return,
+        };
+        let socket = unsafe { (*host).socket };
+
+        let buffer = ENetBuffer {
+            data: WAKE_UP.as_ptr() as *mut c_void,
+            dataLength: WAKE_UP.len(),
+        };
+
+        for _ in self.pending_wake_ups..due {
+            if unsafe { enet_socket_send(socket, &address as *const _, &buffer as *const _, 1) }
+                <= 0
+            {
+                break;
+            }
+
+            self.pending_wake_ups += 1;
+        }
+    }
+
+    /// Handles the datagram `host` just received, see `simulate_datagram`.
+    unsafe fn intercept(&mut self, host: *mut ENetHost) -> InterceptAction {
+        let sender = Address::from_enet_address(&(*host).receivedAddress);
+        let data = std::slice::from_raw_parts((*host).receivedData, (*host).receivedDataLength);
+
+        // only the host itself can wake it up
+        if data != WAKE_UP || Some(&sender) != wake_up_address(host).as_ref() {
+            return self.on_datagram(Instant::now(), &sender, data);
+        }
+
+        // ENet already counted the wake-up as received traffic, while `LoopbackPair::step` expects these
+        // counters to only include datagrams that were actually sent to the host
+        (*host).totalReceivedData = (*host).totalReceivedData.wrapping_sub(WAKE_UP.len() as u32);
+        (*host).totalReceivedPackets = (*host).totalReceivedPackets.wrapping_sub(1);
+
+        self.pending_wake_ups = self.pending_wake_ups.saturating_sub(1);
+        if self.due_count(Instant::now()) == 0 {
+            return InterceptAction::Consume;
+        }
+
+        // hand the due datagram to ENet in place of the wake-up
+        let datagram = self.queue.remove(0);
+        self.current = datagram.data;
+        (*host).receivedAddress = datagram.sender.to_enet_address();
+        (*host).receivedData = self.current.as_mut_ptr();
+        (*host).receivedDataLength = self.current.len();
+
+        InterceptAction::PassThrough
+    }
+}
+
+/// Returns the address `host` sends its wake-up datagrams to, which is the address of its own socket.
+unsafe fn wake_up_address(host: *mut ENetHost) -> Option<Address> {
+    match socket_address(host) {
+        // bound to all interfaces
+        Some(ref a) if a.ip().is_unspecified() => Some(Address::new(Ipv4Addr::LOCALHOST, a.port())),
+        other => other,
+    }
+}
+
+/// Makes a network simulator available to ENet on this thread, until the returned guard is dropped.
+pub(crate) struct ActiveSimulatorGuard {
+    previous: Option<*mut NetworkSimulator>,
+}
+
+impl ActiveSimulatorGuard {
+    pub(crate) fn new(simulator: Option<&mut NetworkSimulator>) -> ActiveSimulatorGuard {
+        let previous =
+            ACTIVE_SIMULATOR.with(|active| active.replace(simulator.map(|s| s as *mut _)));
+
+        ActiveSimulatorGuard { previous }
+    }
+}
+
+impl Drop for ActiveSimulatorGuard {
+    fn drop(&mut self) {
+        ACTIVE_SIMULATOR.with(|active| active.set(self.previous));
+    }
+}
+
+/// Passes the datagram `host` just received through the active simulator, if any.
+///
+/// If the datagram is consumed, it must not be processed any further. Otherwise, the datagram of `host`
+/// may have been replaced by a delayed one.
+pub(crate) unsafe fn simulate_datagram(host: *mut ENetHost) -> InterceptAction {
+    match ACTIVE_SIMULATOR.with(Cell::get) {
+        Some(simulator) => (*simulator).intercept(host),
+        None => InterceptAction::PassThrough,
+    }
+}
+
+/// A small, seedable pseudo-random number generator (xorshift64*).
+#[derive(Debug, Clone)]
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+
+        // the state must never be 0
+        let state = seed ^ MIX;
+        XorShift {
+            state: if state == 0 { MIX } else { state },
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NetworkConditions, NetworkSimulator};
+    use crate::{Address, InterceptAction};
+
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    fn sender() -> Address {
+        Address::new(Ipv4Addr::LOCALHOST, 1234)
+    }
+
+    #[test]
+    fn test_no_conditions() {
+        let mut simulator = NetworkSimulator::new(0, NetworkConditions::default());
+
+        for _ in 0..100 {
+            let action = simulator.on_datagram(Instant::now(), &sender(), b"data");
+            assert_eq!(action, InterceptAction::PassThrough);
+        }
+        assert_eq!(simulator.queued_datagrams(), 0);
+    }
+
+    #[test]
+    fn test_loss_is_reproducible() {
+        let conditions = NetworkConditions {
+            loss: 0.2,
+            ..NetworkConditions::default()
+        };
+        let run = |seed| {
+            let mut simulator = NetworkSimulator::new(seed, conditions.clone());
+            (0..1000)
+                .map(|_| simulator.on_datagram(Instant::now(), &sender(), b"data"))
+                .collect::<Vec<_>>()
+        };
+
+        let actions = run(42);
+        assert_eq!(actions, run(42));
+        assert_ne!(actions, run(43));
+
+        let dropped = actions
+            .iter()
+            .filter(|a| **a == InterceptAction::Consume)
+            .count();
+        assert!(dropped > 150 && dropped < 250, "dropped {}", dropped);
+    }
+
+    #[test]
+    fn test_latency_and_bandwidth() {
+        let mut simulator = NetworkSimulator::new(
+            0,
+            NetworkConditions {
+                latency: Duration::from_millis(50),
+                bandwidth: Some(1000),
+                ..NetworkConditions::default()
+            },
+        );
+        let now = Instant::now();
+
+        assert_eq!(
+            simulator.on_datagram(now, &sender(), &[0; 100]),
+            InterceptAction::Consume
+        );
+        simulator.on_datagram(now, &sender(), &[0; 100]);
+
+        assert_eq!(simulator.due_count(now + Duration::from_millis(149)), 0);
+        assert_eq!(simulator.due_count(now + Duration::from_millis(150)), 1);
+        assert_eq!(simulator.due_count(now + Duration::from_millis(250)), 2);
+    }
+
+    #[test]
+    fn test_peer_conditions() {
+        let mut simulator = NetworkSimulator::new(0, NetworkConditions::default());
+        simulator.set_peer_conditions(
+            sender(),
+            NetworkConditions {
+                loss: 1.0,
+                ..NetworkConditions::default()
+            },
+        );
+        let other = Address::new(Ipv4Addr::LOCALHOST, 4321);
+
+        assert_eq!(
+            simulator.on_datagram(Instant::now(), &sender(), b""),
+            InterceptAction::Consume
+        );
+        assert_eq!(
+            simulator.on_datagram(Instant::now(), &other, b""),
+            InterceptAction::PassThrough
+        );
+        assert_eq!(simulator.stats().dropped, 1);
+    }
+}
@@ -0,0 +1,127 @@
+//! Access to ENet's clock, and clocks for the timeouts of this crate.
+//!
+//! ENet measures time in milliseconds, using a single clock for the whole process. This clock decides when
+//! packets are resent and when peers time out, so moving it forward with `set` or `advance` lets tests trigger
+//! timeouts without sleeping. Note that this affects all `Host`s in the process.
+//!
+//! Timeouts implemented by this crate, e.g. those of `rpc::Rpc`, use a `Clock`, which can follow ENet's clock
+//! (`EnetClock`) or be controlled manually (`ManualClock`). A `Host` does not use a `Clock`: its timers are
+//! implemented by ENet, which always reads ENet's clock, so they can only be moved through `set` and `advance`.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use enet_sys::{enet_time_get, enet_time_set};
+
+/// Returns the current time of ENet's clock in milliseconds. The time wraps around after about 49 days.
+pub fn now() -> u32 {
+    unsafe { enet_time_get() }
+}
+
+/// Sets the current time of ENet's clock in milliseconds, for all `Host`s in this process.
+///
+/// Moving the clock backwards can delay ENet's timers, so it should only be moved forward.
+pub fn set(time_ms: u32) {
+    unsafe {
+        enet_time_set(time_ms);
+    }
+}
+
+/// Moves ENet's clock forward by `duration`, for all `Host`s in this process.
+pub fn advance(duration: Duration) {
+    set(now().wrapping_add(duration.as_millis() as u32));
+}
+
+/// A source of the current time, used for timeouts.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// A `Clock` that follows the real time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A `Clock` that follows ENet's clock, so it moves along with `set` and `advance`.
+#[derive(Debug, Clone, Copy)]
+pub struct EnetClock {
+    base: Instant,
+    base_ms: u32,
+}
+
+impl EnetClock {
+    /// Creates a clock that starts at the current real time.
+    pub fn new() -> EnetClock {
+        EnetClock {
+            base: Instant::now(),
+            base_ms: now(),
+        }
+    }
+}
+
+impl Default for EnetClock {
+    fn default() -> EnetClock {
+        EnetClock::new()
+    }
+}
+
+impl Clock for EnetClock {
+    fn now(&self) -> Instant {
+        self.base + Duration::from_millis(u64::from(now().wrapping_sub(self.base_ms)))
+    }
+}
+
+/// A `Clock` that only moves when it is advanced. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    /// Creates a clock that stands still at the current real time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Moves this clock, and all its clones, forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ManualClock};
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.clone().advance(Duration::from_secs(3));
+        assert_eq!(clock.now() - start, Duration::from_secs(3));
+    }
+}
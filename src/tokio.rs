@@ -0,0 +1,169 @@
+//! Asynchronous driver for a `Host`, based on [tokio](https://tokio.rs).
+//!
+//! An `AsyncHost` registers the socket of a `Host` with the tokio reactor, and services ENet without blocking
+//! whenever the socket becomes readable, packets are queued through an `AsyncSender`, or ENet's next timer fires,
+//! e.g. to resend an unacknowledged packet or to ping an idle peer. An idle `AsyncHost` does not wake up otherwise.
+//!
+//! Like `Host`, an `AsyncHost` can not be sent between threads, so it has to be used on a current-thread runtime
+//! or a `tokio::task::LocalSet`. The `AsyncSender` can be used from any thread.
+//!
+//! This module requires the `tokio` feature.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use ::tokio::io::unix::AsyncFd;
+use ::tokio::sync::mpsc;
+
+use crate::{Error, Event, Host, Packet, PacketMode, PeerId};
+
+/// How many packets can be queued by `AsyncSender`s before `AsyncSender::send` waits.
+const COMMAND_QUEUE_SIZE: usize = 1024;
+
+/// The socket of a `Host`, as registered with the tokio reactor. The socket is owned by ENet, not closed on drop.
+struct HostSocket(RawFd);
+
+impl AsRawFd for HostSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// A packet queued by an `AsyncSender`.
+#[derive(Debug)]
+struct SendCommand {
+    peer_id: PeerId,
+    channel_id: u8,
+    data: Vec<u8>,
+    mode: PacketMode,
+}
+
+/// An error returned by `AsyncSender::send` if the `AsyncHost` has been dropped.
+#[derive(Fail, Debug)]
+#[fail(display = "the AsyncHost has been dropped")]
+pub struct HostClosed;
+
+/// Drives a `Host` using the tokio reactor.
+///
+/// See the module documentation for more information.
+pub struct AsyncHost<T> {
+    // declared before `host`, so the socket is deregistered before ENet closes it
+    socket: AsyncFd<HostSocket>,
+    host: Host<T>,
+
+    commands: mpsc::Receiver<SendCommand>,
+    sender: mpsc::Sender<SendCommand>,
+}
+
+/// A handle to queue packets on an `AsyncHost`, which can be cloned and sent between threads.
+#[derive(Clone, Debug)]
+pub struct AsyncSender {
+    commands: mpsc::Sender<SendCommand>,
+}
+
+impl<T> AsyncHost<T> {
+    /// Registers the socket of `host` with the tokio reactor.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(host: Host<T>) -> io::Result<AsyncHost<T>> {
+        let socket = AsyncFd::new(HostSocket(host.socket_fd()))?;
+        let (sender, commands) = mpsc::channel(COMMAND_QUEUE_SIZE);
+
+        Ok(AsyncHost {
+            socket,
+            host,
+            commands,
+            sender,
+        })
+    }
+
+    /// Returns a reference to the driven `Host`.
+    pub fn host(&self) -> &Host<T> {
+        &self.host
+    }
+
+    /// Returns a mutable reference to the driven `Host`, e.g. to connect to other hosts.
+    pub fn host_mut(&mut self) -> &mut Host<T> {
+        &mut self.host
+    }
+
+    /// Returns a handle that can be used to send packets through this `AsyncHost`.
+    pub fn sender(&self) -> AsyncSender {
+        AsyncSender {
+            commands: self.sender.clone(),
+        }
+    }
+
+    /// Services the `Host` until an event occurs, and returns it.
+    ///
+    /// This method is cancel-safe: if it is used in `tokio::select!` and another branch completes first,
+    /// no events or packets are lost.
+    pub async fn next_event(&mut self) -> Result<Event<'_, T>, Error> {
+        let sys_event = loop {
+            if let Some(sys_event) = self.host.service_sys(0)? {
+                break sys_event;
+            }
+
+            let next_timer = self
+                .host
+                .next_timer_ms()
+                .map(|ms| Duration::from_millis(ms.into()));
+            let command = ::tokio::select! {
+                guard = self.socket.readable() => {
+                    // ENet reads all pending datagrams when it is serviced next
+                    guard.map_err(io_error)?.clear_ready();
+                    None
+                }
+                command = self.commands.recv() => command,
+                _ = ::tokio::time::sleep(next_timer.unwrap_or_default()), if next_timer.is_some() => None,
+            };
+
+            if let Some(command) = command {
+                self.execute(command)?;
+            }
+        };
+
+        Ok(self
+            .host
+            .process_sys_event(&sys_event)
+            .expect("ENet returned an empty event"))
+    }
+
+    /// Queues the packet of `command`. Packets for peers that are not connected anymore are dropped.
+    fn execute(&mut self, command: SendCommand) -> Result<(), Error> {
+        let packet = Packet::from_vec(command.data, command.mode)?;
+
+        match self.host.peer(command.peer_id) {
+            Some(mut peer) => peer.send_packet(packet, command.channel_id),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AsyncSender {
+    /// Queues a packet to be sent to the peer identified by `peer_id`.
+    ///
+    /// Waits if too many packets are queued already. The packet is dropped if the peer is not connected anymore
+    /// when the `AsyncHost` processes it.
+    pub async fn send(
+        &self,
+        peer_id: PeerId,
+        channel_id: u8,
+        data: Vec<u8>,
+        mode: PacketMode,
+    ) -> Result<(), HostClosed> {
+        let command = SendCommand {
+            peer_id,
+            channel_id,
+            data,
+            mode,
+        };
+
+        self.commands.send(command).await.map_err(|_| HostClosed)
+    }
+}
+
+fn io_error(err: io::Error) -> Error {
+    Error(err.raw_os_error().unwrap_or(-1))
+}
@@ -0,0 +1,35 @@
+//! Tests that move ENet's clock. The clock is shared by all hosts in a process, so these tests run in
+//! their own test binary, apart from the tests that rely on ENet's default timeouts.
+
+use std::time::Duration;
+
+use enet::loopback::LoopbackPair;
+use enet::{ChannelLimit, Enet, Event, Packet, PacketMode};
+
+#[test]
+fn test_enet_time_timeout() {
+    let enet = Enet::new().unwrap();
+    let mut pair = LoopbackPair::<()>::new(&enet, 1, ChannelLimit::Maximum).unwrap();
+    pair.connect(1, 0).unwrap();
+    pair.step().unwrap();
+
+    // the client stops responding, as it is not serviced anymore
+    let server = pair.server();
+    let mut peer = server.peers().next().unwrap();
+    peer.set_timeout(0, 500, 1000);
+    let packet = Packet::new(b"lost", PacketMode::ReliableSequenced).unwrap();
+    peer.send_packet(packet, 0).unwrap();
+    server.flush();
+
+    enet::time::advance(Duration::from_secs(2));
+
+    let mut disconnected = false;
+    for _ in 0..10 {
+        if let Some(Event::Disconnect(..)) = server.service(10).unwrap() {
+            disconnected = true;
+            break;
+        }
+    }
+
+    assert!(disconnected);
+}